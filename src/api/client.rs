@@ -1,12 +1,16 @@
 use reqwest::header::HeaderMap;
 use serde_json::to_string;
 
-use crate::{api::models::response_models, config::AudioMode};
+use crate::{api::models::response_models, config::AudioMode, episode::EpisodeNumber};
 
 use super::models::request_models::{
     self, EpisodeVariables, Request, ShowVariables, StreamVariables,
 };
 
+pub const USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 6.1; Win64; rv:109.0) Gecko/20100101 Firefox/109.0";
+pub const REFERER: &str = "https://allanime.to";
+
 #[derive(Debug)]
 pub struct ApiClient {
     client: reqwest::Client,
@@ -44,17 +48,11 @@ impl ApiClient {
         let mut headers = HeaderMap::new();
         headers.insert(
             "User-Agent",
-            "Mozilla/5.0 (Windows NT 6.1; Win64; rv:109.0) Gecko/20100101 Firefox/109.0"
-                .to_owned()
-                .parse()
-                .expect("Failed to parse header value"),
+            USER_AGENT.parse().expect("Failed to parse header value"),
         );
         headers.insert(
             "Referer",
-            "https://allanime.to"
-                .to_owned()
-                .parse()
-                .expect("Failed to parse header value"),
+            REFERER.parse().expect("Failed to parse header value"),
         );
 
         headers
@@ -120,12 +118,7 @@ impl ApiClient {
             .request(reqwest::Method::GET, self.allanime_api())
             .query(&params);
 
-        let response = request
-            .send()
-            .await?
-            .json()
-            .await
-            .expect("Failed to deserialise response");
+        let response = request.send().await?.json().await?;
 
         Ok(response)
     }
@@ -142,7 +135,7 @@ impl ApiClient {
         let episodes_list_gql = r#"
         query ($showId: String!) {
             show(_id: $showId) {
-                _id availableEpisodesDetail
+                _id malId availableEpisodesDetail
             }
         }"#
         .to_owned();
@@ -155,11 +148,37 @@ impl ApiClient {
         }
     }
 
+    pub async fn request_show_details(
+        &self,
+        show_id: String,
+    ) -> Result<response_models::Response, Box<dyn std::error::Error>> {
+        let params = self.build_show_details_params(show_id);
+        self.request_data(params).await
+    }
+
+    fn build_show_details_params(&self, show_id: String) -> Request {
+        let show_details_gql = r#"
+        query ($showId: String!) {
+            show(_id: $showId) {
+                _id name englishName nativeName description thumbnail
+                genres status score airedStart
+            }
+        }"#
+        .to_owned();
+
+        let variables = EpisodeVariables { show_id };
+
+        Request {
+            variables: to_string(&variables).expect("Failed to serialise show variables"),
+            query: show_details_gql,
+        }
+    }
+
     pub async fn request_streams(
         &self,
         show_id: String,
         audio_mode: &AudioMode,
-        episode: u32,
+        episode: &EpisodeNumber,
     ) -> Result<response_models::Response, Box<dyn std::error::Error>> {
         let params = self.build_search_stream_params(show_id, audio_mode, episode);
         self.request_data(params).await
@@ -169,7 +188,7 @@ impl ApiClient {
         &self,
         show_id: String,
         audio_mode: &AudioMode,
-        episode: u32,
+        episode: &EpisodeNumber,
     ) -> Request {
         let episode_embed_gql = r#"
         query(
@@ -190,7 +209,7 @@ impl ApiClient {
         let variables = StreamVariables {
             show_id,
             translation_type: *audio_mode,
-            episode_string: episode.to_string(),
+            episode_string: episode.as_str().to_owned(),
         };
 
         Request {
@@ -19,12 +19,38 @@ pub struct Show {
     #[serde(rename = "_id")]
     pub id: String,
     pub name: Option<String>,
+    /// MyAnimeList id, used to look up skip times
+    #[serde(rename = "malId", default)]
+    pub mal_id: Option<String>,
     #[serde(rename = "availableEpisodes")]
     pub available_episodes: Option<Episodes>,
     #[serde(rename = "availableEpisodesDetail")]
     pub available_episodes_detail: Option<EpisodesDetails>,
     #[serde(rename = "__typename", default)]
     pub typename: Option<String>,
+    #[serde(rename = "englishName", default)]
+    pub english_name: Option<String>,
+    #[serde(rename = "nativeName", default)]
+    pub native_name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub genres: Option<Vec<String>>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub score: Option<f32>,
+    #[serde(rename = "airedStart", default)]
+    pub aired_start: Option<AiredDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct AiredDate {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub date: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -96,13 +122,13 @@ struct RawUrls {
     duration: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Subtitles {
-    lang: String,
-    label: String,
-    default: bool,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Subtitles {
+    pub lang: String,
+    pub label: String,
+    pub default: bool,
     paring: String,
-    src: String,
+    pub src: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -123,6 +149,12 @@ pub struct Link {
     trusts: Option<Box<[String]>>,
 }
 
+impl Link {
+    pub fn subtitles(&self) -> &[Subtitles] {
+        self.subtitles.as_deref().unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StreamResponse {
     pub links: Vec<Link>,
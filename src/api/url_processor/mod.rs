@@ -1,7 +1,55 @@
 use std::collections::HashMap;
 
+use super::client::{REFERER, USER_AGENT};
+
 pub mod decrypt;
 
+/// HTTP headers a stream has to be requested with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamHeaders(Vec<(String, String)>);
+
+impl StreamHeaders {
+    /// Headers required by the CDNs serving resolved streams
+    ///
+    /// These are global rather than per provider: every provider behind the API checks the
+    /// same Referer and browser User-Agent the API client sends, so players and download
+    /// tools have to send them too.
+    pub fn global() -> Self {
+        StreamHeaders(vec![
+            ("Referer".to_owned(), REFERER.to_owned()),
+            ("User-Agent".to_owned(), USER_AGENT.to_owned()),
+        ])
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn referer(&self) -> Option<&str> {
+        self.get("Referer")
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.get("User-Agent")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Headers as `Name: value` lines
+    pub fn lines(&self) -> Vec<String> {
+        self.iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect()
+    }
+}
+
 pub async fn handle_source(
     client: &reqwest::Client,
     link: &String,
@@ -51,7 +99,7 @@ async fn handle_vipanicdn_anifastcdn(
             let quality: Option<u32> = episode
                 .split(".m3u8")
                 .next()
-                .and_then(|s| s.split('.').last())
+                .and_then(|s| s.split('.').next_back())
                 .and_then(|s| s.parse().ok());
 
             quality.map(|q| (q, format!("{}/{}", relative_link, episode)))
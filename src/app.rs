@@ -1,8 +1,14 @@
-use std::io::{self, Error, ErrorKind};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    api::client::ApiClient, app_state::AppState, config::Config, menu::error_menu,
-    player::AppPlayer, utils::is_command_available,
+    api::client::ApiClient,
+    app_state::AppState,
+    config::{Config, ConfigLayers, Source},
+    downloader::AppDownloader,
+    player::AppPlayer,
 };
 
 #[derive(Debug)]
@@ -10,6 +16,9 @@ pub struct App {
     state: AppState,
     client: ApiClient,
     player: AppPlayer,
+    downloader: AppDownloader,
+    config_path: Option<PathBuf>,
+    config: ConfigLayers,
 }
 
 impl App {
@@ -24,6 +33,16 @@ impl App {
         &self.player
     }
 
+    pub fn downloader(&self) -> &AppDownloader {
+        &self.downloader
+    }
+
+    /// Config file the settings were read from, and menu changes are saved to
+    /// `None` when no config directory could be found
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
     pub fn mut_state(&mut self) -> &mut AppState {
         &mut self.state
     }
@@ -32,27 +51,59 @@ impl App {
         &mut self.player
     }
 
-    pub async fn initialise_app() -> Result<Self, io::Error> {
+    /// Every source the settings were merged from
+    pub fn config(&self) -> &ConfigLayers {
+        &self.config
+    }
+
+    /// `overrides` are the settings given on the command line
+    pub async fn initialise_app(
+        config_path: Option<&Path>,
+        overrides: Config,
+    ) -> Result<Self, io::Error> {
         // Load app configuration
-        let config = match Config::load_configuration() {
-            Ok(config) => config,
-            Err(_) => Config::default(),
+        let config_path = match Config::locate(config_path) {
+            Ok(config_path) => Some(config_path),
+            Err(e) => {
+                eprintln!("Warning: {}, using default settings", e);
+                None
+            }
+        };
+        let file = match config_path.as_deref().map(Config::load_configuration) {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                eprintln!("Warning: {}", e);
+                eprintln!("Using default settings, run `anirust config validate` for details");
+                Config::default()
+            }
+            None => Config::default(),
         };
+        let mut layers = ConfigLayers::default();
+        layers.push(Source::File, file);
+        let (env, errors) = Config::from_env();
+        for e in errors {
+            eprintln!("Warning: {}", e);
+        }
+        layers.push(Source::Env, env);
+        layers.push(Source::Cli, overrides);
+        Self::from_layers(config_path, layers)
+    }
 
-        let mut app = App {
+    /// App set up from settings that have already been gathered
+    pub fn from_layers(
+        config_path: Option<PathBuf>,
+        layers: ConfigLayers,
+    ) -> Result<Self, io::Error> {
+        let config = layers.merged();
+
+        // The media player is checked before playing, downloads don't need one
+        Ok(App {
             state: AppState::from_config(&config)?,
             client: ApiClient::default(),
             player: AppPlayer::from_config(&config),
-        };
-
-        let media_player = app.player().media_player();
-        // Check if the media player is available
-        if !is_command_available(media_player.as_str()) {
-            let error_string = format!("Could not find media player: {}", media_player.as_str());
-            let error = Box::new(Error::new(ErrorKind::Other, error_string));
-            error_menu(&mut app, error).await;
-        }
-
-        Ok(app)
+            downloader: AppDownloader::from_config(&config),
+            config_path,
+            config: layers,
+        })
     }
 }
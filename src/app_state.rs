@@ -5,13 +5,24 @@ use std::{
 
 use indexmap::IndexMap;
 
-use crate::config::{AudioMode, Config, Quality};
+use crate::{
+    api::{models::response_models::Subtitles, url_processor::StreamHeaders},
+    config::{AudioMode, Config, Layout, PickerKind, Quality},
+    episode::EpisodeNumber,
+    paths,
+    utils::picker::{picker_for, Picker},
+};
 
 #[derive(Debug)]
 pub struct AppState {
     quality: Quality,
     audio_mode: AudioMode,
     download_dir: PathBuf,
+    layout: Layout,
+    force_stream: bool,
+    interactive: bool,
+    picker: PickerKind,
+    wraparound: bool,
     show_query: ShowQuery,
     current_show: Option<CurrentShow>,
     current_episode: Option<CurrentEpisode>,
@@ -21,28 +32,54 @@ pub struct AppState {
 #[derive(Debug)]
 pub struct ShowQuery {
     title: Option<String>,
-    episode: Option<u32>,
+    episode: Option<EpisodeNumber>,
+    pick: ShowPick,
 }
 impl ShowQuery {
     pub fn title(&self) -> Option<&str> {
         self.title.as_deref()
     }
-    pub fn episode(&self) -> Option<u32> {
-        self.episode
+    pub fn episode(&self) -> Option<&EpisodeNumber> {
+        self.episode.as_ref()
+    }
+    pub fn pick(&self) -> &ShowPick {
+        &self.pick
     }
 }
 
+/// How a show is chosen when the search finds several
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShowPick {
+    /// Ask, or take the best match for the title when not interactive
+    #[default]
+    Prompt,
+    /// Nth result, counting from 1
+    Index(usize),
+    First,
+    /// The result named exactly like the title
+    Exact,
+}
+
 #[derive(Debug)]
 pub struct CurrentShow {
     id: String,
     name: Option<String>,
-    available_episodes: Vec<u32>,
+    mal_id: Option<String>,
+    available_episodes: Vec<EpisodeNumber>,
 }
 impl CurrentShow {
-    pub fn new(id: String, name: Option<String>, available_episodes: Vec<u32>) -> Self {
+    pub fn new(
+        id: String,
+        name: Option<String>,
+        mal_id: Option<String>,
+        mut available_episodes: Vec<EpisodeNumber>,
+    ) -> Self {
+        available_episodes.sort();
+        available_episodes.dedup();
         CurrentShow {
             id,
             name,
+            mal_id,
             available_episodes,
         }
     }
@@ -55,44 +92,130 @@ impl CurrentShow {
         self.name.as_deref()
     }
 
-    pub fn available_episodes(&self) -> &Vec<u32> {
+    pub fn mal_id(&self) -> Option<&str> {
+        self.mal_id.as_deref()
+    }
+
+    /// Episodes in ascending order
+    pub fn available_episodes(&self) -> &Vec<EpisodeNumber> {
         &self.available_episodes
     }
+
+    /// First available episode after `ep_number`
+    pub fn episode_after(&self, ep_number: &EpisodeNumber) -> Option<&EpisodeNumber> {
+        self.available_episodes.iter().find(|&ep| ep > ep_number)
+    }
+
+    /// Available episodes from `from` to `to`, inclusive
+    pub fn episodes_between(&self, from: &EpisodeNumber, to: &EpisodeNumber) -> Vec<EpisodeNumber> {
+        self.available_episodes
+            .iter()
+            .filter(|&ep| ep >= from && ep <= to)
+            .cloned()
+            .collect()
+    }
+
+    /// Last available episode before `ep_number`
+    pub fn episode_before(&self, ep_number: &EpisodeNumber) -> Option<&EpisodeNumber> {
+        self.available_episodes
+            .iter()
+            .rev()
+            .find(|&ep| ep < ep_number)
+    }
 }
 
 #[derive(Debug)]
 pub struct CurrentEpisode {
-    ep_number: u32,
+    ep_number: EpisodeNumber,
     url: String,
+    headers: StreamHeaders,
+    local: bool,
+    resume_at: Option<f64>,
+    subtitles: Vec<Subtitles>,
 }
 
 impl CurrentEpisode {
-    pub fn new(ep_number: u32, url: String) -> Self {
-        CurrentEpisode { ep_number, url }
+    pub fn new(ep_number: EpisodeNumber, url: String, headers: StreamHeaders) -> Self {
+        CurrentEpisode {
+            ep_number,
+            url,
+            headers,
+            local: false,
+            resume_at: None,
+            subtitles: Vec::new(),
+        }
+    }
+
+    /// Episode backed by a file in the download directory
+    pub fn local(ep_number: EpisodeNumber, path: PathBuf) -> Self {
+        CurrentEpisode {
+            ep_number,
+            url: path.to_string_lossy().into_owned(),
+            headers: StreamHeaders::default(),
+            local: true,
+            resume_at: None,
+            subtitles: Vec::new(),
+        }
     }
 
-    pub fn ep_number(&self) -> u32 {
-        self.ep_number
+    pub fn ep_number(&self) -> &EpisodeNumber {
+        &self.ep_number
     }
 
     pub fn url(&self) -> &String {
         &self.url
     }
+
+    /// Headers the stream has to be requested with
+    pub fn headers(&self) -> &StreamHeaders {
+        &self.headers
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    /// Position in seconds playback should start from
+    pub fn resume_at(&self) -> Option<f64> {
+        self.resume_at
+    }
+
+    pub fn set_resume_at(&mut self, resume_at: Option<f64>) {
+        self.resume_at = resume_at;
+    }
+
+    /// Subtitle tracks offered alongside the stream
+    pub fn subtitles(&self) -> &[Subtitles] {
+        &self.subtitles
+    }
+
+    pub fn set_subtitles(&mut self, subtitles: Vec<Subtitles>) {
+        self.subtitles = subtitles;
+    }
+
+    /// URL of the provider's default subtitle track, or the first one
+    pub fn default_subtitle(&self) -> Option<&str> {
+        self.subtitles()
+            .iter()
+            .find(|subtitle| subtitle.default)
+            .or(self.subtitles().first())
+            .map(|subtitle| subtitle.src.as_str())
+    }
 }
 
 impl AppState {
     pub fn from_config(config: &Config) -> Result<Self, io::Error> {
         let state = config.state().cloned().unwrap_or_default();
         // Get the home directory for the current user
-        let mut download_dir = dirs::home_dir().ok_or(io::Error::new(
+        let home = dirs::home_dir().ok_or(io::Error::new(
             ErrorKind::NotFound,
             "Home directory not found",
         ))?;
 
-        // Append the provided file name to the configuration directory
-        download_dir.push(state.download_dir.unwrap_or("anime".to_owned()));
+        // Relative paths are under the home directory, `~` and variables are expanded
+        let download_dir = paths::resolve(&home, &state.download_dir.unwrap_or("anime".to_owned()));
 
-        let known_providers: IndexMap<String, String> = vec![
+        let mut known_providers: IndexMap<String, String> = vec![
             ("Default".to_string(), "wixmp".to_string()),
             ("Sak".to_string(), "dropbox".to_string()),
             ("Kir".to_string(), "wetransfer".to_string()),
@@ -101,13 +224,38 @@ impl AppState {
         ]
         .into_iter()
         .collect();
+        if let Some(provider) = &state.provider {
+            match known_providers.iter().position(|(name, host)| {
+                name.eq_ignore_ascii_case(provider) || host.eq_ignore_ascii_case(provider)
+            }) {
+                Some(index) => known_providers.move_index(index, 0),
+                None => eprintln!(
+                    "Warning: unknown provider {}, expected one of {}",
+                    provider,
+                    known_providers
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
         Ok(AppState {
             quality: state.quality.unwrap_or_default(),
             audio_mode: state.audio_mode.unwrap_or_default(),
             download_dir,
+            layout: config
+                .download()
+                .and_then(|download| download.layout)
+                .unwrap_or_default(),
+            force_stream: false,
+            interactive: true,
+            picker: state.picker.unwrap_or_default(),
+            wraparound: state.wraparound.unwrap_or(false),
             show_query: ShowQuery {
                 title: None,
                 episode: None,
+                pick: ShowPick::default(),
             },
             current_show: None,
             current_episode: None,
@@ -133,30 +281,36 @@ impl AppState {
         self.current_episode.as_ref()
     }
 
-    pub fn next_episode(&self) -> u32 {
-        let current_episode = self.current_episode().expect("episode selected").ep_number;
-        let available_episodes_len = self
-            .current_show()
-            .expect("show selected")
-            .available_episodes
-            .len() as u32;
-        if current_episode < available_episodes_len {
-            current_episode + 1
-        } else {
-            1
-        }
+    /// Episode following the current one, wrapping to the first only when enabled
+    pub fn next_episode(&self) -> Option<EpisodeNumber> {
+        let current_episode = self
+            .current_episode()
+            .expect("episode selected")
+            .ep_number();
+        let show = self.current_show().expect("show selected");
+        show.episode_after(current_episode)
+            .or_else(|| {
+                self.wraparound
+                    .then(|| show.available_episodes().first())
+                    .flatten()
+            })
+            .cloned()
     }
 
-    pub fn previous_episode(&self) -> u32 {
-        let current_episode = self.current_episode().expect("episode selected").ep_number;
-        if current_episode > 1_u32 {
-            current_episode - 1
-        } else {
-            self.current_show()
-                .expect("show selected")
-                .available_episodes
-                .len() as u32
-        }
+    /// Episode preceding the current one, wrapping to the last only when enabled
+    pub fn previous_episode(&self) -> Option<EpisodeNumber> {
+        let current_episode = self
+            .current_episode()
+            .expect("episode selected")
+            .ep_number();
+        let show = self.current_show().expect("show selected");
+        show.episode_before(current_episode)
+            .or_else(|| {
+                self.wraparound
+                    .then(|| show.available_episodes().last())
+                    .flatten()
+            })
+            .cloned()
     }
 
     pub fn quality(&self) -> &Quality {
@@ -171,6 +325,81 @@ impl AppState {
         &self.download_dir
     }
 
+    pub fn force_stream(&self) -> bool {
+        self.force_stream
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Directory holding every downloaded episode of a show
+    pub fn show_dir(&self, show_name: &str) -> PathBuf {
+        self.download_dir().join(show_name)
+    }
+
+    /// Path a downloaded episode is stored at, following the configured layout
+    ///
+    /// Dubbed and raw downloads are marked in the file name so they don't pass for the
+    /// subbed episode, subbed downloads keep the plain name.
+    pub fn episode_path(&self, show_name: &str, ep_number: &EpisodeNumber) -> PathBuf {
+        let translation = match self.audio_mode() {
+            AudioMode::Sub => "",
+            AudioMode::Dub => " (dub)",
+            AudioMode::Raw => " (raw)",
+        };
+        let mut path = self.show_dir(show_name);
+        match self.layout() {
+            Layout::Default => path.push(format!(
+                "{} Episode {}{}.mp4",
+                show_name, ep_number, translation
+            )),
+            Layout::MediaServer => {
+                path.push("Season 01");
+                path.push(format!(
+                    "{} - S01E{}{}.mp4",
+                    show_name,
+                    ep_number.padded(2),
+                    translation
+                ));
+            }
+        }
+        path
+    }
+
+    /// Finished download for the episode in the current audio mode, unless streaming is forced
+    pub fn local_episode(
+        &self,
+        show: &CurrentShow,
+        ep_number: &EpisodeNumber,
+    ) -> Option<CurrentEpisode> {
+        if self.force_stream() {
+            return None;
+        }
+        let path = self.episode_path(show.name()?, ep_number);
+        // aria2c keeps a control file next to downloads that are still in progress
+        let control_file = PathBuf::from(format!("{}.aria2", path.display()));
+        if path.is_file() && !control_file.exists() {
+            Some(CurrentEpisode::local(ep_number.clone(), path))
+        } else {
+            None
+        }
+    }
+
+    /// Switch the current episode to its downloaded copy if one has appeared
+    pub fn prefer_local_episode(&mut self) {
+        let local = match (self.current_show(), self.current_episode()) {
+            (Some(show), Some(episode)) if !episode.is_local() => self
+                .local_episode(show, episode.ep_number())
+                .map(|local| (local, episode.resume_at())),
+            _ => None,
+        };
+        if let Some((mut local, resume_at)) = local {
+            local.set_resume_at(resume_at);
+            self.current_episode = Some(local);
+        }
+    }
+
     pub fn show_query(&self) -> &ShowQuery {
         &self.show_query
     }
@@ -187,7 +416,134 @@ impl AppState {
         self.audio_mode = audio_mode
     }
 
-    pub fn set_show_query(&mut self, title: Option<String>, episode: Option<u32>) {
-        self.show_query = ShowQuery { title, episode };
+    pub fn set_force_stream(&mut self, force_stream: bool) {
+        self.force_stream = force_stream
+    }
+
+    pub fn set_show_query(&mut self, title: Option<String>, episode: Option<EpisodeNumber>) {
+        self.show_query = ShowQuery {
+            title,
+            episode,
+            pick: ShowPick::default(),
+        };
+    }
+
+    pub fn set_show_pick(&mut self, pick: ShowPick) {
+        self.show_query.pick = pick;
+    }
+
+    /// Whether pickers and menus may be shown
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive
+    }
+
+    /// Menu every choice is made in
+    pub fn picker(&self) -> Box<dyn Picker> {
+        picker_for(&self.picker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_from(contents: &str) -> AppState {
+        let config: Config = toml::from_str(contents).expect("Valid config");
+        AppState::from_config(&config).expect("State from config")
+    }
+
+    /// Episodes 1, 12, 12.5 and 14, given out of order with a duplicate
+    fn show() -> CurrentShow {
+        CurrentShow::new(
+            "show".to_owned(),
+            Some("Show".to_owned()),
+            None,
+            ["14", "12.5", "1", "12", "14"]
+                .into_iter()
+                .map(EpisodeNumber::new)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn neighbouring_episodes_skip_gaps_and_stop_at_the_ends() {
+        let show = show();
+        let ep = EpisodeNumber::new;
+        assert_eq!(
+            show.available_episodes(),
+            &[ep("1"), ep("12"), ep("12.5"), ep("14")]
+        );
+        assert_eq!(show.episode_after(&ep("1")), Some(&ep("12")));
+        assert_eq!(show.episode_after(&ep("12")), Some(&ep("12.5")));
+        assert_eq!(show.episode_after(&ep("12.5")), Some(&ep("14")));
+        assert_eq!(show.episode_after(&ep("13")), Some(&ep("14")));
+        assert_eq!(show.episode_after(&ep("14")), None);
+        assert_eq!(show.episode_before(&ep("14")), Some(&ep("12.5")));
+        assert_eq!(show.episode_before(&ep("12.5")), Some(&ep("12")));
+        assert_eq!(show.episode_before(&ep("12")), Some(&ep("1")));
+        assert_eq!(show.episode_before(&ep("1")), None);
+    }
+
+    #[test]
+    fn next_and_previous_wrap_around_only_when_enabled() {
+        let at = |state: &mut AppState, episode: &str| {
+            state.set_episode(CurrentEpisode::new(
+                EpisodeNumber::new(episode),
+                String::new(),
+                StreamHeaders::default(),
+            ))
+        };
+        let ep = |episode| Some(EpisodeNumber::new(episode));
+
+        let mut state = state_from("[state]\n");
+        state.set_show(show());
+        at(&mut state, "12");
+        assert_eq!(state.next_episode(), ep("12.5"));
+        assert_eq!(state.previous_episode(), ep("1"));
+        at(&mut state, "14");
+        assert_eq!(state.next_episode(), None);
+        assert_eq!(state.previous_episode(), ep("12.5"));
+        at(&mut state, "1");
+        assert_eq!(state.previous_episode(), None);
+
+        let mut state = state_from("[state]\nwraparound = true\n");
+        state.set_show(show());
+        at(&mut state, "14");
+        assert_eq!(state.next_episode(), ep("1"));
+        at(&mut state, "1");
+        assert_eq!(state.previous_episode(), ep("14"));
+        assert_eq!(state.next_episode(), ep("12"));
+    }
+
+    #[test]
+    fn episode_paths_follow_the_layout_and_audio_mode() {
+        let mut state = state_from("[state]\ndownload_dir = \"/anime\"\n");
+        let episode = EpisodeNumber::new("5");
+        assert_eq!(
+            state.episode_path("Show", &episode),
+            PathBuf::from("/anime/Show/Show Episode 5.mp4")
+        );
+        state.set_audio_mode(AudioMode::Dub);
+        assert_eq!(
+            state.episode_path("Show", &episode),
+            PathBuf::from("/anime/Show/Show Episode 5 (dub).mp4")
+        );
+
+        let mut state = state_from(
+            "[state]\ndownload_dir = \"/anime\"\n\n[download]\nlayout = \"media-server\"\n",
+        );
+        assert_eq!(
+            state.episode_path("Show", &episode),
+            PathBuf::from("/anime/Show/Season 01/Show - S01E05.mp4")
+        );
+        state.set_audio_mode(AudioMode::Raw);
+        assert_eq!(
+            state.episode_path("Show", &episode),
+            PathBuf::from("/anime/Show/Season 01/Show - S01E05 (raw).mp4")
+        );
     }
 }
@@ -1,4 +1,12 @@
-use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::{
+    app_state::ShowPick,
+    config::{AudioMode, Config, MediaPlayer, PickerKind, Player, Quality, State},
+    episode::EpisodeNumber,
+};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -6,6 +14,71 @@ pub struct AnirustArgs {
     /// first argument
     #[clap(subcommand)]
     pub mode_type: ModeType,
+    /// always stream, even if the episode has been downloaded
+    #[clap(long, global = true)]
+    pub stream: bool,
+    /// play the next episode automatically when the current one is finished
+    #[clap(long, global = true)]
+    pub binge: bool,
+    /// never open a picker: choose automatically or fail listing the candidates
+    #[clap(long, global = true)]
+    pub non_interactive: bool,
+    /// menu used for choices, overriding the configuration
+    #[clap(long, value_enum, global = true)]
+    pub picker: Option<PickerKind>,
+    /// watch the dubbed version for this run
+    #[clap(long, global = true, conflicts_with_all = ["sub", "raw"])]
+    pub dub: bool,
+    /// watch the subbed version for this run
+    #[clap(long, global = true, conflicts_with = "raw")]
+    pub sub: bool,
+    /// watch the untranslated version for this run
+    #[clap(long, global = true)]
+    pub raw: bool,
+    /// stream quality for this run
+    #[clap(long, value_enum, global = true)]
+    pub quality: Option<Quality>,
+    /// media player for this run
+    #[clap(long, value_enum, global = true)]
+    pub player: Option<MediaPlayer>,
+    /// download directory for this run, relative to home unless absolute
+    #[clap(long, value_name = "PATH", global = true)]
+    pub download_dir: Option<String>,
+    /// source to try first for this run, by name (Sak) or host (dropbox)
+    #[clap(long, value_name = "NAME", global = true)]
+    pub provider: Option<String>,
+    /// config file to use, also read from ANIRUST_CONFIG
+    #[clap(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+}
+
+impl AnirustArgs {
+    /// Settings given as flags, which win over the config file
+    pub fn overrides(&self) -> Config {
+        let audio_mode = if self.dub {
+            Some(AudioMode::Dub)
+        } else if self.sub {
+            Some(AudioMode::Sub)
+        } else if self.raw {
+            Some(AudioMode::Raw)
+        } else {
+            None
+        };
+        Config::layer(
+            State {
+                quality: self.quality,
+                audio_mode,
+                download_dir: self.download_dir.clone(),
+                wraparound: None,
+                picker: self.picker,
+                provider: self.provider.clone(),
+            },
+            Player {
+                media_player: self.player.clone(),
+                ..Player::default()
+            },
+        )
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -14,16 +87,41 @@ pub enum ModeType {
     Menu,
     /// Search for anime
     Search(SearchCommand),
+    /// Continue watching a show from the watch history
+    Continue,
     /// Download single or range of episodes
     Download(DownloadCommand),
+    /// Build an M3U playlist for a range of episodes and play it
+    Playlist(PlaylistCommand),
+    /// List or delete watch history
+    History(HistoryCommand),
+    /// Print search results and resolved streams for scripts
+    Query(QueryCommand),
+    /// Print the stream URL of an episode
+    Url(UrlCommand),
+    /// Create, inspect and check the configuration file
+    Config(ConfigCommand),
+}
+
+impl ModeType {
+    /// Whether the command can end up starting the media player
+    pub fn plays(&self) -> bool {
+        match self {
+            ModeType::Menu | ModeType::Search(_) | ModeType::Continue => true,
+            ModeType::Playlist(command) => command.output.is_none(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Args)]
 pub struct SearchCommand {
     /// anime title
     pub title: String,
-    /// episode number
-    pub episode: Option<u32>,
+    /// episode number, e.g. 12 or 12.5
+    pub episode: Option<EpisodeNumber>,
+    #[clap(flatten)]
+    pub selection: ShowSelection,
 }
 
 #[derive(Debug, Args)]
@@ -31,7 +129,152 @@ pub struct DownloadCommand {
     /// anime title
     pub title: String,
     /// range of episodes; start
-    pub from: u32,
+    pub from: EpisodeNumber,
     /// range of episodes; end
-    pub to: Option<u32>,
+    pub to: Option<EpisodeNumber>,
+    #[clap(flatten)]
+    pub selection: ShowSelection,
+}
+
+#[derive(Debug, Args)]
+pub struct PlaylistCommand {
+    /// anime title
+    pub title: String,
+    /// range of episodes; start
+    pub from: EpisodeNumber,
+    /// range of episodes; end
+    pub to: Option<EpisodeNumber>,
+    /// write the playlist to a file, or `-` for stdout, instead of playing it
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+    #[clap(flatten)]
+    pub selection: ShowSelection,
+}
+
+/// How a show is picked from the search results without asking
+#[derive(Debug, Args)]
+pub struct ShowSelection {
+    /// pick the Nth search result, counting from 1
+    #[clap(long, value_name = "N", conflicts_with_all = ["first", "exact"])]
+    pub select: Option<usize>,
+    /// pick the first search result
+    #[clap(long, conflicts_with = "exact")]
+    pub first: bool,
+    /// only accept a show whose name is exactly the title
+    #[clap(long)]
+    pub exact: bool,
+}
+
+impl ShowSelection {
+    pub fn pick(&self) -> ShowPick {
+        match self.select {
+            Some(index) => ShowPick::Index(index),
+            None if self.first => ShowPick::First,
+            None if self.exact => ShowPick::Exact,
+            None => ShowPick::Prompt,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryCommand {
+    #[clap(subcommand)]
+    pub action: Option<HistoryAction>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HistoryAction {
+    /// List watched shows, most recent first
+    List {
+        /// only shows whose name contains this text
+        filter: Option<String>,
+    },
+    /// Delete entries whose name contains the text or whose id matches
+    Delete {
+        /// show name or id
+        filter: String,
+    },
+    /// Delete the whole history
+    Clear,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Write a commented configuration file with every setting
+    Init {
+        /// replace an existing file
+        #[clap(long)]
+        force: bool,
+    },
+    /// Print the settings in effect and where each one comes from
+    Show,
+    /// Report unknown keys and invalid values
+    Validate,
+    /// Open the configuration file in $VISUAL or $EDITOR
+    Edit,
+}
+
+#[derive(Debug, Args)]
+pub struct UrlCommand {
+    /// anime title
+    pub title: String,
+    /// episode number, e.g. 12 or 12.5
+    pub episode: EpisodeNumber,
+    /// also print the headers the stream needs and subtitle URLs
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// copy the URL to the clipboard with wl-copy or xclip
+    #[clap(short, long)]
+    pub copy: bool,
+    #[clap(flatten)]
+    pub selection: ShowSelection,
+}
+
+#[derive(Debug, Args)]
+pub struct QueryCommand {
+    #[clap(subcommand)]
+    pub target: QueryTarget,
+    /// output format
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QueryTarget {
+    /// Shows matching a title
+    Shows {
+        /// anime title
+        title: String,
+    },
+    /// Episodes of a show in every translation
+    Episodes {
+        /// show id, as printed by `query shows`
+        show_id: String,
+    },
+    /// Stream sources of an episode, highest priority first
+    Sources {
+        /// show id, as printed by `query shows`
+        show_id: String,
+        /// episode number, e.g. 12 or 12.5
+        episode: EpisodeNumber,
+    },
+    /// Qualities and URLs every source of an episode resolves to
+    Url {
+        /// show id, as printed by `query shows`
+        show_id: String,
+        /// episode number, e.g. 12 or 12.5
+        episode: EpisodeNumber,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
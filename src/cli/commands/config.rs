@@ -0,0 +1,207 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process::Command,
+};
+
+use crate::{
+    cli::args::{ConfigAction, ConfigCommand},
+    config::{Config, ConfigError, ConfigLayers},
+};
+
+const TEMPLATE: &str = r#"# anirust configuration, every setting is optional
+# Uncomment a line to change it from the default shown
+# Any key can also be set with ANIRUST_<SECTION>_<KEY>, e.g. ANIRUST_STATE_QUALITY=worst
+
+[state]
+# quality = "best"            # "best" or "worst"
+# audio_mode = "sub"          # "sub", "dub" or "raw"
+# download_dir = "anime"      # relative to home, or absolute; ~ and $VARS are expanded
+# wraparound = false          # Next/Previous jump between the last and first episode
+# picker = "skim"             # "skim", "fzf", "rofi", "dmenu", "bemenu" or "stdin"
+# provider = "Default"        # source tried first: "Default", "Sak", "Kir", "S-mp4" or "Luf-mp4"
+
+[player]
+# media_player = "mpv"        # "iina", "mpv", "vlc" or "custom", detected when unset
+# custom_command = ["celluloid", "{url}"]
+# wait = false                # return to the menu only once the player has closed
+# proxy = false               # serve streams through a local proxy that adds headers
+
+# [player.extra_args]
+# mpv = ["--fs"]
+
+[download]
+# backend = "aria2c"          # "aria2c" or "aria2-rpc"
+# layout = "default"          # "default" or "media-server"
+# rpc_url = "http://localhost:6800/jsonrpc"
+# rpc_secret = ""
+# rpc_dir = ""
+
+[hooks]
+# on_episode_downloaded = ""
+# on_batch_complete = ""
+
+[skip]
+# enabled = true              # look up opening and ending times on AniSkip
+# auto = false                # skip without waiting for the key
+# api_url = "https://api.aniskip.com"
+# key = "TAB"
+"#;
+
+pub fn config_command(path: Option<&Path>, layers: &ConfigLayers, command: ConfigCommand) {
+    let result = path
+        .ok_or(ConfigError::DirectoryNotFound)
+        .and_then(|path| match command.action {
+            ConfigAction::Init { force } => init(path, force),
+            ConfigAction::Show => show(path, layers),
+            ConfigAction::Validate => validate(path),
+            ConfigAction::Edit => edit(path),
+        });
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn init(path: &Path, force: bool) -> Result<(), ConfigError> {
+    if path.exists() && !force {
+        return Err(ConfigError::AlreadyExists(path.to_owned()));
+    }
+    write_template(path)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn write_template(path: &Path) -> Result<(), ConfigError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(ConfigError::IoError)?;
+    }
+    fs::write(path, TEMPLATE).map_err(ConfigError::IoError)
+}
+
+fn show(path: &Path, layers: &ConfigLayers) -> Result<(), ConfigError> {
+    show_to(&mut io::stdout().lock(), path, layers)
+}
+
+/// A reader that stops early, like `head`, is not an error
+fn show_to(out: &mut impl Write, path: &Path, layers: &ConfigLayers) -> Result<(), ConfigError> {
+    match write_settings(out, path, layers) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(ConfigError::IoError),
+    }
+}
+
+fn write_settings(out: &mut impl Write, path: &Path, layers: &ConfigLayers) -> io::Result<()> {
+    writeln!(out, "# {}", path.display())?;
+    let mut section = None;
+    for setting in layers.settings() {
+        if section != Some(setting.section.clone()) {
+            writeln!(out, "\n[{}]", setting.section)?;
+            section = Some(setting.section.clone());
+        }
+        writeln!(
+            out,
+            "{} = {}  # {}",
+            setting.key, setting.value, setting.source
+        )?;
+    }
+    out.flush()
+}
+
+/// Exits with failure when anything is wrong, so it can be used in scripts
+fn validate(path: &Path) -> Result<(), ConfigError> {
+    if !path.exists() {
+        println!("{} does not exist, defaults are used", path.display());
+        return Ok(());
+    }
+    if !report_problems(path)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print what [`Config::validate`] finds, `true` when the file is fine
+fn report_problems(path: &Path) -> Result<bool, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(ConfigError::IoError)?;
+    let problems = Config::validate(&contents);
+    if problems.is_empty() {
+        println!("{} is valid", path.display());
+    }
+    for problem in &problems {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    Ok(problems.is_empty())
+}
+
+fn edit(path: &Path) -> Result<(), ConfigError> {
+    if !path.exists() {
+        write_template(path)?;
+    }
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or("vi".to_owned());
+    // Editors are often set with arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(ConfigError::IoError)?;
+    if !status.success() {
+        eprintln!("{} {}", program, status);
+    }
+    report_problems(path).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts `left` bytes, then fails like a pipe whose reader has gone
+    struct ClosingPipe {
+        left: usize,
+    }
+
+    impl Write for ClosingPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let written = buf.len().min(self.left);
+            self.left -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn settings_are_listed_by_section_with_their_source() {
+        let mut out = Vec::new();
+        show_to(
+            &mut out,
+            Path::new("/home/user/.config/anirust/config.toml"),
+            &ConfigLayers::default(),
+        )
+        .expect("Settings shown");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("# /home/user/.config/anirust/config.toml\n\n[download]\n"));
+        assert!(out.contains("\n[state]\n"));
+        assert!(out.contains("\nquality = \"best\"  # default\n"));
+    }
+
+    #[test]
+    fn a_closed_pipe_ends_the_listing_quietly() {
+        let result = show_to(
+            &mut ClosingPipe { left: 20 },
+            Path::new("/home/user/.config/anirust/config.toml"),
+            &ConfigLayers::default(),
+        );
+        assert!(result.is_ok());
+    }
+}
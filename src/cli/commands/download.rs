@@ -1,96 +1,207 @@
-use std::{fmt, fs, path::Path, process::Command};
+use std::{
+    fmt, fs, io,
+    path::Path,
+    process::{Command, ExitStatus},
+};
 
-use crate::{app::App, cli::args::DownloadCommand, menu::error_menu, utils::is_command_available};
+use crate::{
+    api::url_processor::StreamHeaders,
+    app::App,
+    cli::args::DownloadCommand,
+    config::{DownloadBackend, Layout},
+    episode::EpisodeNumber,
+    menu::error_menu,
+    nfo,
+    utils::is_command_available,
+};
 
 use std::error::Error;
 
-use super::search::{fetch_episode, fetch_show};
+use super::search::{fetch_episode, fetch_show, ApiError};
 
 #[derive(Debug)]
 pub enum DownloadError {
-    StreamNotSelected,
     ShowNotSelected,
     EpisodeNotSelected,
     DirectoryCreationFailed,
     RequiredToolMissing(String),
+    RpcFailed(String),
+    ToolFailed(String),
 }
 
 impl fmt::Display for DownloadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::StreamNotSelected => write!(f, "Stream has not been selected"),
             Self::ShowNotSelected => write!(f, "Show has not been selected"),
             Self::EpisodeNotSelected => write!(f, "Episode has not been selected"),
             Self::DirectoryCreationFailed => write!(f, "Failed to create download directory"),
             Self::RequiredToolMissing(tool) => write!(f, "`{}` is required for download", tool),
+            Self::RpcFailed(message) => write!(f, "aria2 RPC download failed: {}", message),
+            Self::ToolFailed(message) => write!(f, "Download failed: {}", message),
         }
     }
 }
 
 impl Error for DownloadError {}
 
+/// What [`download`] did with the current episode
+#[derive(Debug, PartialEq)]
+pub enum Downloaded {
+    Fetched,
+    /// The episode was already in the library, nothing was downloaded
+    AlreadyPresent,
+}
+
 pub async fn download_command(app: &mut App, command: DownloadCommand) {
     app.mut_state().set_show_query(Some(command.title), None);
-    let episode_range = match command.to {
-        Some(to_ep) => command.from..=to_ep,
-        None => command.from..=command.from,
-    };
+    app.mut_state().set_show_pick(command.selection.pick());
+    let to = command.to.unwrap_or(command.from.clone());
     match fetch_show(app.state(), app.client()).await {
         Ok(show) => app.mut_state().set_show(show),
         Err(err) => error_menu(app, err).await,
     };
+    let episode_range = app
+        .state()
+        .current_show()
+        .expect("Show selected")
+        .episodes_between(&command.from, &to);
+    if episode_range.is_empty() {
+        let error = ApiError::NoEpisodes(format!("No episodes from {} to {}", command.from, to));
+        error_menu(app, error).await;
+    }
 
+    if !download_episodes(app, episode_range).await {
+        std::process::exit(1);
+    }
+}
+
+/// Download each episode, then hand the ones fetched to the batch hook
+///
+/// Returns whether every episode is now in the library.
+async fn download_episodes(app: &mut App, episode_range: Vec<EpisodeNumber>) -> bool {
+    let mut downloaded = Vec::new();
+    let mut failed = false;
     for ep_number in episode_range {
         match fetch_episode(
             app.state(),
             app.client(),
             app.state().current_show().expect("Show selected"),
-            Some(ep_number),
+            Some(ep_number.clone()),
         )
         .await
         {
             Ok(episode) => {
                 app.mut_state().set_episode(episode);
-                let _ = download(app);
+                match download(app).await {
+                    Ok(Downloaded::Fetched) => downloaded.push(ep_number),
+                    Ok(Downloaded::AlreadyPresent) => {
+                        eprintln!("Episode {} is already downloaded", ep_number)
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        failed = true;
+                    }
+                }
+            }
+            // One missing stream shouldn't stop the rest of the batch
+            Err(e) => {
+                eprintln!("Skipping episode {}: {}", ep_number, e);
+                failed = true;
             }
-            Err(err) => error_menu(app, err).await,
         }
     }
+
+    // Only episodes fetched by this batch are handed to the hook
+    if !downloaded.is_empty() {
+        let show = app.state().current_show().expect("Show selected");
+        let show_name = show.name().expect("Show has name");
+        let show_dir = app.state().show_dir(show_name);
+        app.downloader()
+            .hooks()
+            .batch_complete(&show_dir, show_name, show.id(), &downloaded);
+    }
+    !failed
 }
 
-pub fn download(app: &App) -> Result<(), DownloadError> {
-    let url = app
+pub async fn download(app: &App) -> Result<Downloaded, DownloadError> {
+    let current_episode = app
         .state()
         .current_episode()
-        .ok_or(DownloadError::StreamNotSelected)?
-        .url();
-    let show_name = app
+        .ok_or(DownloadError::EpisodeNotSelected)?;
+    // Nothing to fetch when playing from the library already
+    if current_episode.is_local() {
+        return Ok(Downloaded::AlreadyPresent);
+    }
+    let url = current_episode.url();
+    let show = app
         .state()
         .current_show()
-        .ok_or(DownloadError::ShowNotSelected)?
-        .name()
-        .expect("Show has name");
-    let episode = app
-        .state()
-        .current_episode()
-        .ok_or(DownloadError::EpisodeNotSelected)?
-        .ep_number();
-    let filename = format!("{} Episode {}", show_name, episode);
-    let mut download_dir = app.state().download_dir().clone();
-    download_dir.push(show_name);
+        .ok_or(DownloadError::ShowNotSelected)?;
+    let show_name = show.name().expect("Show has name");
+    let episode = current_episode.ep_number();
+    let output_path = app.state().episode_path(show_name, episode);
+    let download_dir = output_path
+        .parent()
+        .expect("Episode path has a parent directory");
 
     // Ensure download directory exists
-    if !Path::new(&download_dir).exists() {
-        fs::create_dir_all(&download_dir).map_err(|_| DownloadError::DirectoryCreationFailed)?;
+    if !download_dir.exists() {
+        fs::create_dir_all(download_dir).map_err(|_| DownloadError::DirectoryCreationFailed)?;
+    }
+
+    fetch_file(app, url, current_episode.headers(), &output_path).await?;
+    if *app.state().layout() == Layout::MediaServer {
+        write_metadata(app, show.id(), show_name, &output_path, episode).await;
+    }
+    app.downloader()
+        .hooks()
+        .episode_downloaded(&output_path, show_name, show.id(), episode);
+    Ok(Downloaded::Fetched)
+}
+
+/// Write the `.nfo` files media servers read, warning instead of failing the download
+async fn write_metadata(
+    app: &App,
+    show_id: &str,
+    show_name: &str,
+    episode_path: &Path,
+    episode: &EpisodeNumber,
+) {
+    if let Err(e) = nfo::write_episode_nfo(episode_path, show_name, episode) {
+        eprintln!("Warning: could not write episode metadata: {}", e);
     }
 
+    let show_dir = app.state().show_dir(show_name);
+    if show_dir.join("tvshow.nfo").exists() {
+        return;
+    }
+    let details = app
+        .client()
+        .request_show_details(show_id.to_owned())
+        .await
+        .ok()
+        .and_then(|response| response.data.show);
+    match details {
+        Some(details) => {
+            if let Err(e) = nfo::write_show_nfo(&show_dir, &details) {
+                eprintln!("Warning: could not write show metadata: {}", e);
+            }
+        }
+        None => eprintln!("Warning: could not fetch metadata for {}", show_name),
+    }
+}
+
+async fn fetch_file(
+    app: &App,
+    url: &str,
+    headers: &StreamHeaders,
+    output_path: &Path,
+) -> Result<(), DownloadError> {
     if url.contains("m3u8") {
         if is_command_available("yt-dlp") {
-            yt_dlp_download(&download_dir, &filename, url);
-            return Ok(());
+            return yt_dlp_download(output_path, url, headers);
         } else if is_command_available("ffmpeg") {
-            ffmpeg_download(&download_dir, &filename, url);
-            return Ok(());
+            return ffmpeg_download(output_path, url, headers);
         } else {
             return Err(DownloadError::RequiredToolMissing(
                 "yt-dlp or ffmpeg".to_owned(),
@@ -98,17 +209,27 @@ pub fn download(app: &App) -> Result<(), DownloadError> {
         }
     }
     // Default
+    if *app.downloader().backend() == DownloadBackend::Aria2Rpc {
+        return app
+            .downloader()
+            .rpc()
+            .download(url, headers, app.state().download_dir(), output_path)
+            .await
+            .map_err(DownloadError::RpcFailed);
+    }
     if is_command_available("aria2c") {
-        aria2c_download(filename, url, &download_dir);
-        Ok(())
+        aria2c_download(output_path, url, headers)
     } else {
         Err(DownloadError::RequiredToolMissing("aria2c".to_owned()))
     }
 }
 
-fn aria2c_download(filename: String, url: &str, download_dir: &std::path::Path) {
-    let output_path = format!("{}.mp4", filename);
-    let _ = Command::new("aria2c")
+fn aria2c_download(
+    output_path: &Path,
+    url: &str,
+    headers: &StreamHeaders,
+) -> Result<(), DownloadError> {
+    let status = Command::new("aria2c")
         .args([
             "--enable-rpc=false",
             "--check-certificate=false",
@@ -120,50 +241,121 @@ fn aria2c_download(filename: String, url: &str, download_dir: &std::path::Path)
             "16",
             url,
             "--dir",
-            download_dir
-                .to_str()
-                .expect("Could not convert download_dir to &str"),
-            "-o",
-            &output_path,
-            "--download-result=hide",
         ])
+        .arg(output_path.parent().expect("Episode path has a parent"))
+        .arg("-o")
+        .arg(
+            output_path
+                .file_name()
+                .expect("Episode path has a file name"),
+        )
+        .arg("--download-result=hide")
+        .args(
+            headers
+                .lines()
+                .iter()
+                .map(|line| format!("--header={}", line)),
+        )
         .spawn()
         .expect("Failed to run aria2c")
         .wait();
+    check_status("aria2c", status)
 }
 
-fn ffmpeg_download(download_dir: &std::path::PathBuf, filename: &String, url: &str) {
-    let output_path = format!("{:?}/{}.mp4", download_dir, filename);
-    let _ = Command::new("ffmpeg")
-        .args([
-            "-loglevel",
-            "error",
-            "-stats",
-            "-i",
-            url,
-            "-c",
-            "copy",
-            &output_path,
-        ])
+fn ffmpeg_download(
+    output_path: &Path,
+    url: &str,
+    headers: &StreamHeaders,
+) -> Result<(), DownloadError> {
+    // ffmpeg takes every header in one CRLF separated argument, which must precede the input
+    let header_block: String = headers
+        .lines()
+        .into_iter()
+        .map(|line| line + "\r\n")
+        .collect();
+    let status = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-stats", "-headers", &header_block])
+        .args(["-i", url, "-c", "copy"])
+        .arg(output_path)
         .spawn()
         .expect("Failed to run ffmpeg")
         .wait();
+    check_status("ffmpeg", status)
 }
 
-fn yt_dlp_download(download_dir: &std::path::PathBuf, filename: &String, url: &str) {
-    let output_path = format!("{:?}/{}.mp4", download_dir, filename);
-    let _ = Command::new("yt-dlp")
-        .args([
-            url,
-            "--no-skip-unavailable-fragments",
-            "--fragment-retries",
-            "infinite",
-            "-N",
-            "16",
-            "-o",
-            &output_path,
-        ])
-        .spawn()
-        .expect("Failed to run yt-dlp")
-        .wait();
+fn yt_dlp_download(
+    output_path: &Path,
+    url: &str,
+    headers: &StreamHeaders,
+) -> Result<(), DownloadError> {
+    let status =
+        Command::new("yt-dlp")
+            .args([
+                url,
+                "--no-skip-unavailable-fragments",
+                "--fragment-retries",
+                "infinite",
+                "-N",
+                "16",
+                "-o",
+            ])
+            .arg(output_path)
+            .args(headers.iter().flat_map(|(name, value)| {
+                ["--add-header".to_owned(), format!("{}:{}", name, value)]
+            }))
+            .spawn()
+            .expect("Failed to run yt-dlp")
+            .wait();
+    check_status("yt-dlp", status)
+}
+
+fn check_status(tool: &str, status: io::Result<ExitStatus>) -> Result<(), DownloadError> {
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(DownloadError::ToolFailed(format!("{} {}", tool, status))),
+        Err(e) => Err(DownloadError::ToolFailed(format!("{}: {}", tool, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        app_state::CurrentShow,
+        config::{Config, ConfigLayers, Source},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn episodes_already_in_the_library_are_not_handed_to_hooks() {
+        let dir = std::env::temp_dir().join(format!("anirust-test-batch-{}", std::process::id()));
+        let marker = dir.join("hook-ran");
+        fs::create_dir_all(dir.join("Show")).unwrap();
+        for episode in ["1", "2"] {
+            fs::write(dir.join(format!("Show/Show Episode {}.mp4", episode)), "").unwrap();
+        }
+        let config: Config = toml::from_str(&format!(
+            "[state]\ndownload_dir = '{0}'\n\n[hooks]\non_episode_downloaded = 'touch {1}'\non_batch_complete = 'touch {1}'\n",
+            dir.display(),
+            marker.display()
+        ))
+        .expect("Valid config");
+        let mut layers = ConfigLayers::default();
+        layers.push(Source::File, config);
+        let mut app = App::from_layers(Some(PathBuf::from("/nonexistent/config.toml")), layers)
+            .expect("App from config");
+        let episodes: Vec<EpisodeNumber> = ["1", "2"].into_iter().map(EpisodeNumber::new).collect();
+        app.mut_state().set_show(CurrentShow::new(
+            "abc".to_owned(),
+            Some("Show".to_owned()),
+            None,
+            episodes.clone(),
+        ));
+
+        assert!(download_episodes(&mut app, episodes).await);
+        assert!(!marker.exists(), "No hook runs without new episodes");
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
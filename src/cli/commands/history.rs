@@ -0,0 +1,70 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    cli::args::{HistoryAction, HistoryCommand},
+    history::{History, HistoryEntry},
+    utils::format_position,
+};
+
+pub fn history_command(command: HistoryCommand) {
+    let result = match command
+        .action
+        .unwrap_or(HistoryAction::List { filter: None })
+    {
+        HistoryAction::List { filter } => History::load().map(|history| {
+            history
+                .entries()
+                .iter()
+                .filter(|entry| filter.as_deref().is_none_or(|f| entry.matches(f)))
+                .for_each(|entry| println!("{}", format_entry(entry)))
+        }),
+        HistoryAction::Delete { filter } => {
+            let mut removed = 0;
+            History::update(|history| removed = history.remove(&filter))
+                .map(|_| println!("Deleted {} entries", removed))
+        }
+        HistoryAction::Clear => History::update(|history| history.clear()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn format_entry(entry: &HistoryEntry) -> String {
+    let progress = match (entry.finished, entry.position, entry.duration) {
+        (true, _, _) => "watched".to_owned(),
+        (false, Some(position), Some(duration)) => {
+            format!(
+                "{}/{}",
+                format_position(position),
+                format_position(duration)
+            )
+        }
+        (false, Some(position), None) => format_position(position),
+        _ => "not started".to_owned(),
+    };
+    format!(
+        "{:<12} {} ({:?})  episode {}  {}",
+        time_ago(entry.timestamp),
+        entry.name,
+        entry.audio_mode,
+        entry.episode,
+        progress
+    )
+}
+
+fn time_ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(timestamp);
+    let elapsed = now.saturating_sub(timestamp);
+    match elapsed {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{}m ago", elapsed / 60),
+        3600..=86399 => format!("{}h ago", elapsed / 3600),
+        _ => format!("{}d ago", elapsed / 86400),
+    }
+}
@@ -1,2 +1,8 @@
+pub mod config;
 pub mod download;
+pub mod history;
+pub mod playlist;
+pub mod query;
+pub mod resume;
 pub mod search;
+pub mod url;
@@ -0,0 +1,177 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    api::url_processor::StreamHeaders, app::App, app_state::CurrentEpisode,
+    cli::args::PlaylistCommand, menu::error_menu, player::proxy::StreamProxy,
+};
+
+use super::search::{fetch_episode, fetch_show, ApiError};
+
+pub async fn playlist_command(app: &mut App, command: PlaylistCommand) {
+    app.mut_state().set_show_query(Some(command.title), None);
+    app.mut_state().set_show_pick(command.selection.pick());
+    let to = command.to.unwrap_or(command.from.clone());
+    match fetch_show(app.state(), app.client()).await {
+        Ok(show) => app.mut_state().set_show(show),
+        Err(err) => error_menu(app, err).await,
+    };
+    let show = app.state().current_show().expect("Show selected");
+    let show_name = show.name().unwrap_or(show.id()).to_owned();
+    let episode_range = show.episodes_between(&command.from, &to);
+    if episode_range.is_empty() {
+        let error = ApiError::NoEpisodes(format!("No episodes from {} to {}", command.from, to));
+        error_menu(app, error).await;
+    }
+
+    let mut episodes = Vec::new();
+    for ep_number in episode_range {
+        let show = app.state().current_show().expect("Show selected");
+        match fetch_episode(app.state(), app.client(), show, Some(ep_number.clone())).await {
+            Ok(episode) => episodes.push(episode),
+            // One missing stream shouldn't cost the rest of the evening
+            Err(e) => eprintln!("Skipping episode {}: {}", ep_number, e),
+        }
+    }
+    if episodes.is_empty() {
+        let error = ApiError::NoStream("No episode in the range could be resolved".to_owned());
+        error_menu(app, error).await;
+    }
+
+    let result = match command.output.as_deref() {
+        Some(path) if path == Path::new("-") => {
+            io::stdout().write_all(build_m3u(&show_name, &episodes, None).as_bytes())
+        }
+        Some(path) => fs::write(path, build_m3u(&show_name, &episodes, None)),
+        None => play(app, &show_name, &episodes).await,
+    };
+    if let Err(e) = result {
+        error_menu(app, e).await;
+    }
+}
+
+async fn play(app: &mut App, show_name: &str, episodes: &[CurrentEpisode]) -> io::Result<()> {
+    // Entries come from the same provider, so the first stream's headers cover the rest
+    let headers = episodes
+        .iter()
+        .find(|episode| !episode.is_local())
+        .map(|episode| episode.headers().clone());
+    let proxy = match headers.clone() {
+        Some(headers) if app.player().proxy() => Some(StreamProxy::start(headers).await?),
+        _ => None,
+    };
+    let playlist = build_m3u(show_name, episodes, proxy.as_ref());
+    let status = app
+        .player()
+        .play_playlist(&playlist, show_name, &headers.unwrap_or_default(), proxy)
+        .await?;
+    app.mut_player().set_last_exit(status);
+    Ok(())
+}
+
+/// Extended M3U with a title per entry
+///
+/// Headers are added as `#EXTVLCOPT` lines, which vlc applies to the entry that follows.
+/// Streams go through `proxy` instead when given, which sends the headers itself.
+fn build_m3u(show_name: &str, episodes: &[CurrentEpisode], proxy: Option<&StreamProxy>) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for episode in episodes {
+        playlist.push_str(&format!(
+            "#EXTINF:-1,{} Episode {}\n",
+            show_name,
+            episode.ep_number()
+        ));
+        match proxy {
+            Some(proxy) if !episode.is_local() => playlist.push_str(&proxy.url_for(episode.url())),
+            _ => {
+                playlist.push_str(&vlc_options(episode.headers()));
+                playlist.push_str(episode.url());
+            }
+        }
+        playlist.push('\n');
+    }
+    playlist
+}
+
+fn vlc_options(headers: &StreamHeaders) -> String {
+    let mut options = String::new();
+    if let Some(referer) = headers.referer() {
+        options.push_str(&format!("#EXTVLCOPT:http-referrer={}\n", referer));
+    }
+    if let Some(user_agent) = headers.user_agent() {
+        options.push_str(&format!("#EXTVLCOPT:http-user-agent={}\n", user_agent));
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::episode::EpisodeNumber;
+
+    use super::*;
+
+    fn stream(episode: &str) -> CurrentEpisode {
+        CurrentEpisode::new(
+            EpisodeNumber::new(episode),
+            format!("https://cdn.example.com/{}.mp4", episode),
+            StreamHeaders::global(),
+        )
+    }
+
+    #[test]
+    fn entries_carry_titles_and_vlc_header_options() {
+        let local = CurrentEpisode::local(
+            EpisodeNumber::new("2"),
+            PathBuf::from("/anime/Show/Show Episode 2.mp4"),
+        );
+        let headers = StreamHeaders::global();
+        assert_eq!(
+            build_m3u("Show", &[stream("1"), local], None),
+            format!(
+                "#EXTM3U\n\
+                 #EXTINF:-1,Show Episode 1\n\
+                 #EXTVLCOPT:http-referrer={}\n\
+                 #EXTVLCOPT:http-user-agent={}\n\
+                 https://cdn.example.com/1.mp4\n\
+                 #EXTINF:-1,Show Episode 2\n\
+                 /anime/Show/Show Episode 2.mp4\n",
+                headers.referer().unwrap(),
+                headers.user_agent().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn vlc_options_cover_only_referer_and_user_agent() {
+        assert_eq!(vlc_options(&StreamHeaders::default()), "");
+        let options = vlc_options(&StreamHeaders::global());
+        assert_eq!(options.lines().count(), 2);
+        assert!(options
+            .lines()
+            .all(|line| line.starts_with("#EXTVLCOPT:http-")));
+    }
+
+    #[tokio::test]
+    async fn proxied_entries_carry_no_headers() {
+        let proxy = StreamProxy::start(StreamHeaders::global())
+            .await
+            .expect("Proxy started");
+        let local = CurrentEpisode::local(
+            EpisodeNumber::new("2"),
+            PathBuf::from("/anime/Show/Show Episode 2.mp4"),
+        );
+        let playlist = build_m3u("Show", &[stream("1"), local], Some(&proxy));
+        assert_eq!(
+            playlist,
+            format!(
+                "#EXTM3U\n#EXTINF:-1,Show Episode 1\n{}\n#EXTINF:-1,Show Episode 2\n/anime/Show/Show Episode 2.mp4\n",
+                proxy.url_for("https://cdn.example.com/1.mp4")
+            )
+        );
+    }
+}
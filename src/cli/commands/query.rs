@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    api::{
+        client::ApiClient,
+        models::response_models::{EpisodesDetails, Show, SourceUrl},
+        url_processor::{decrypt::decrypt_url, handle_source},
+    },
+    app::App,
+    cli::args::{OutputFormat, QueryCommand, QueryTarget},
+    config::AudioMode,
+    episode::EpisodeNumber,
+    menu::error_menu,
+};
+
+use super::search::{fetch_sources, search_shows, ApiError};
+
+// `--output json` prints these rather than the API's own structs, so scripts see a stable
+// schema with only decoded fields
+
+/// A search result, with the id the other queries take
+#[derive(Debug, Serialize)]
+struct ShowOutput {
+    id: String,
+    name: Option<String>,
+    english_name: Option<String>,
+    /// MyAnimeList id
+    mal_id: Option<String>,
+}
+
+impl From<&Show> for ShowOutput {
+    fn from(show: &Show) -> Self {
+        ShowOutput {
+            id: show.id.clone(),
+            name: show.name.clone(),
+            english_name: show.english_name.clone(),
+            mal_id: show.mal_id.clone(),
+        }
+    }
+}
+
+/// Episodes available in each audio mode, as the API orders them
+#[derive(Debug, Serialize)]
+struct EpisodesOutput {
+    sub: Vec<EpisodeNumber>,
+    dub: Vec<EpisodeNumber>,
+    raw: Vec<EpisodeNumber>,
+}
+
+impl From<&EpisodesDetails> for EpisodesOutput {
+    fn from(details: &EpisodesDetails) -> Self {
+        let episodes = |list: &[String]| list.iter().map(EpisodeNumber::new).collect::<Vec<_>>();
+        EpisodesOutput {
+            sub: episodes(&details.sub),
+            dub: episodes(&details.dub),
+            raw: episodes(&details.raw),
+        }
+    }
+}
+
+/// A source of the episode before it is resolved
+#[derive(Debug, Serialize)]
+struct SourceOutput {
+    source_name: String,
+    priority: Option<f32>,
+    /// `iframe` or `player` as the API reports it
+    #[serde(rename = "type")]
+    kind: String,
+    /// Decrypted source URL, `None` when it could not be decrypted
+    url: Option<String>,
+}
+
+impl From<&SourceUrl> for SourceOutput {
+    fn from(source: &SourceUrl) -> Self {
+        SourceOutput {
+            source_name: source.source_name.clone(),
+            priority: source.priority,
+            kind: source.type_field.clone(),
+            url: decrypt_url(source.source_url.clone()).ok(),
+        }
+    }
+}
+
+/// A source of the episode and the links it resolved to
+#[derive(Debug, Serialize)]
+struct ResolvedSource {
+    source_name: String,
+    priority: Option<f32>,
+    links: Vec<ResolvedLink>,
+}
+
+/// Stream URLs of a link, keyed by vertical resolution
+#[derive(Debug, Serialize)]
+struct ResolvedLink {
+    link: String,
+    qualities: BTreeMap<u32, String>,
+}
+
+pub async fn query_command(app: &mut App, command: QueryCommand) {
+    // Scripts get an exit status instead of the error menu
+    app.mut_state().set_interactive(false);
+    if let Err(e) = query(app, command).await {
+        error_menu(app, e).await;
+    }
+}
+
+async fn query(app: &App, command: QueryCommand) -> Result<(), ApiError> {
+    let output = command.output;
+    let audio_mode = app.state().audio_mode();
+    match command.target {
+        QueryTarget::Shows { title } => {
+            let shows = search_shows(app.client(), title).await?;
+            let json: Vec<ShowOutput> = shows.edges.iter().map(ShowOutput::from).collect();
+            print(output, &json, || {
+                shows
+                    .edges
+                    .iter()
+                    .map(|show| format!("{}\t{}", show.id, show.name.as_deref().unwrap_or("")))
+                    .collect()
+            })
+        }
+        QueryTarget::Episodes { show_id } => {
+            let episodes = fetch_episodes_details(app.client(), show_id).await?;
+            print(output, &EpisodesOutput::from(&episodes), || {
+                [
+                    ("sub", &episodes.sub),
+                    ("dub", &episodes.dub),
+                    ("raw", &episodes.raw),
+                ]
+                .iter()
+                .map(|(mode, list)| format!("{}\t{}", mode, list.join(" ")))
+                .collect()
+            })
+        }
+        QueryTarget::Sources { show_id, episode } => {
+            let sources = fetch_sources(app.client(), show_id, audio_mode, &episode).await?;
+            let json: Vec<SourceOutput> = sources.iter().map(SourceOutput::from).collect();
+            print(output, &json, || {
+                sources
+                    .iter()
+                    .map(|source| {
+                        format!(
+                            "{}\t{}\t{}",
+                            source.source_name,
+                            source.priority.unwrap_or_default(),
+                            source.type_field
+                        )
+                    })
+                    .collect()
+            })
+        }
+        QueryTarget::Url { show_id, episode } => {
+            let resolved = resolve_sources(app.client(), show_id, audio_mode, &episode).await?;
+            print(output, &resolved, || {
+                resolved
+                    .iter()
+                    .flat_map(|source| {
+                        source.links.iter().flat_map(move |link| {
+                            link.qualities.iter().map(move |(quality, url)| {
+                                format!("{}\t{}\t{}", source.source_name, quality, url)
+                            })
+                        })
+                    })
+                    .collect()
+            })
+        }
+    }
+    Ok(())
+}
+
+/// JSON as pretty printed by serde, text as one tab separated line per item
+fn print<T: Serialize>(output: OutputFormat, value: &T, text: impl FnOnce() -> Vec<String>) {
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(value).expect("Query results serialise")
+        ),
+        OutputFormat::Text => {
+            for line in text() {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+async fn fetch_episodes_details(
+    client: &ApiClient,
+    show_id: String,
+) -> Result<EpisodesDetails, ApiError> {
+    let response = client
+        .request_episodes(show_id)
+        .await
+        .map_err(|_| ApiError::ClientError("Failed to request episodes".to_owned()))?;
+    response
+        .data
+        .show
+        .and_then(|show| show.available_episodes_detail)
+        .ok_or(ApiError::MissingField("No episodes details".to_owned()))
+}
+
+/// Follow every source of an episode down to its quality map
+///
+/// Sources that fail to decrypt or resolve are kept with no links, so the output shows
+/// which providers were tried.
+async fn resolve_sources(
+    client: &ApiClient,
+    show_id: String,
+    audio_mode: &AudioMode,
+    episode: &EpisodeNumber,
+) -> Result<Vec<ResolvedSource>, ApiError> {
+    let sources = fetch_sources(client, show_id, audio_mode, episode).await?;
+    let mut resolved = Vec::new();
+    for source in sources {
+        let links = resolve_links(client, &source).await;
+        resolved.push(ResolvedSource {
+            source_name: source.source_name,
+            priority: source.priority,
+            links,
+        });
+    }
+    Ok(resolved)
+}
+
+async fn resolve_links(client: &ApiClient, source: &SourceUrl) -> Vec<ResolvedLink> {
+    let Ok(path) = decrypt_url(source.source_url.clone()) else {
+        return Vec::new();
+    };
+    let Ok(response) = client.request_links(&path).await else {
+        return Vec::new();
+    };
+    let mut links = Vec::new();
+    for link in response.links.into_iter().map(|link| link.link) {
+        if let Some(qualities) = handle_source(client.client(), &link).await {
+            links.push(ResolvedLink {
+                link,
+                qualities: qualities.into_iter().collect(),
+            });
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn shows_keep_only_their_ids_and_names() {
+        let show: Show = serde_json::from_value(json!({
+            "_id": "ReooPAxPMsHM4KPMY",
+            "name": "One Piece",
+            "englishName": "One Piece",
+            "malId": "21",
+            "availableEpisodes": {"sub": 1100, "dub": 1000, "raw": 0},
+            "__typename": "Show",
+            "thumbnail": "https://example.com/thumb.jpg"
+        }))
+        .expect("API show");
+        assert_eq!(
+            serde_json::to_value(ShowOutput::from(&show)).unwrap(),
+            json!({
+                "id": "ReooPAxPMsHM4KPMY",
+                "name": "One Piece",
+                "english_name": "One Piece",
+                "mal_id": "21"
+            })
+        );
+    }
+
+    #[test]
+    fn episodes_are_listed_per_audio_mode() {
+        let details = EpisodesDetails {
+            sub: vec!["2".to_owned(), "1".to_owned(), "1.5".to_owned()],
+            dub: vec!["1".to_owned()],
+            raw: Vec::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(EpisodesOutput::from(&details)).unwrap(),
+            json!({"sub": ["2", "1", "1.5"], "dub": ["1"], "raw": []})
+        );
+    }
+
+    #[test]
+    fn sources_show_the_decrypted_url() {
+        let source: SourceUrl = serde_json::from_value(json!({
+            "sourceUrl": "--175948514e4c4f57175b54575b5307515c05595a5b",
+            "priority": 7.9,
+            "sourceName": "Default",
+            "type": "iframe",
+            "className": "",
+            "streamerId": "allanime"
+        }))
+        .expect("API source");
+        let output = serde_json::to_value(SourceOutput::from(&source)).unwrap();
+        assert_eq!(output["source_name"], "Default");
+        assert_eq!(output["type"], "iframe");
+        assert_eq!(output["url"], "/apivtwo/clock.json?id=abc");
+        let mut keys: Vec<&String> = output.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["priority", "source_name", "type", "url"]);
+    }
+
+    #[test]
+    fn resolved_links_are_keyed_by_quality() {
+        let resolved = ResolvedSource {
+            source_name: "Default".to_owned(),
+            priority: Some(7.9),
+            links: vec![ResolvedLink {
+                link: "https://cdn.example.com/master.m3u8".to_owned(),
+                qualities: BTreeMap::from([
+                    (1080, "https://cdn.example.com/1080.m3u8".to_owned()),
+                    (720, "https://cdn.example.com/720.m3u8".to_owned()),
+                ]),
+            }],
+        };
+        let output = serde_json::to_value(resolved).unwrap();
+        assert_eq!(
+            output["links"][0]["qualities"],
+            json!({
+                "720": "https://cdn.example.com/720.m3u8",
+                "1080": "https://cdn.example.com/1080.m3u8"
+            })
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::{error::Error, fmt};
+
+use crate::{
+    app::App,
+    app_state::CurrentShow,
+    history::{History, HistoryEntry, HistoryError},
+    menu::{run, Screen},
+    utils::picker::Picker,
+};
+
+use super::{
+    history::format_entry,
+    search::{fetch_episode, search_episodes, ApiError},
+};
+
+#[derive(Debug)]
+pub enum ResumeError {
+    EmptyHistory,
+    AllWatched(String),
+    History(HistoryError),
+    Api(ApiError),
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EmptyHistory => write!(f, "Nothing to continue, watch history is empty"),
+            Self::AllWatched(name) => write!(f, "All available episodes of {} watched", name),
+            Self::History(e) => write!(f, "{}", e),
+            Self::Api(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ResumeError {}
+
+impl From<ApiError> for ResumeError {
+    fn from(e: ApiError) -> Self {
+        ResumeError::Api(e)
+    }
+}
+
+pub async fn continue_command(app: &mut App) {
+    run(app, Screen::Continue).await;
+}
+
+/// Pick a show from the watch history and make the episode to continue from current
+pub async fn resume(app: &mut App) -> Result<(), ResumeError> {
+    let entry = select_entry(
+        History::load().map_err(ResumeError::History)?,
+        app.state().interactive().then(|| app.state().picker()),
+    )?;
+    app.mut_state().set_audio_mode(entry.audio_mode);
+
+    let (available_episodes, mal_id) =
+        search_episodes(app.client(), entry.show_id.clone(), &entry.audio_mode).await?;
+    let show = CurrentShow::new(
+        entry.show_id.clone(),
+        Some(entry.name.clone()),
+        mal_id,
+        available_episodes,
+    );
+
+    let (ep_number, resume_at) = if entry.finished {
+        let next = show
+            .episode_after(&entry.episode)
+            .ok_or(ResumeError::AllWatched(entry.name.clone()))?;
+        (next.clone(), None)
+    } else {
+        (entry.episode.clone(), entry.position)
+    };
+
+    let mut episode = fetch_episode(app.state(), app.client(), &show, Some(ep_number)).await?;
+    episode.set_resume_at(resume_at);
+    app.mut_state().set_show(show);
+    app.mut_state().set_episode(episode);
+    Ok(())
+}
+
+/// Without a picker the most recently watched show is continued
+fn select_entry(
+    history: History,
+    picker: Option<Box<dyn Picker>>,
+) -> Result<HistoryEntry, ResumeError> {
+    let Some(picker) = picker else {
+        return history
+            .entries()
+            .first()
+            .cloned()
+            .ok_or(ResumeError::EmptyHistory);
+    };
+    let options: Vec<(String, &HistoryEntry)> = history
+        .entries()
+        .iter()
+        .map(|entry| (format_entry(entry), entry))
+        .collect();
+    if options.is_empty() {
+        return Err(ResumeError::EmptyHistory);
+    }
+    let display_strings: Vec<&str> = options.iter().map(|(s, _)| s.as_str()).collect();
+    let selection = picker
+        .pick(&display_strings, Some("Continue watching: "))
+        .ok_or(ResumeError::Api(ApiError::NotSelected(
+            "no show was selected".to_owned(),
+        )))?;
+    options
+        .into_iter()
+        .find_map(|(s, entry)| {
+            if s == selection {
+                Some(entry.clone())
+            } else {
+                None
+            }
+        })
+        .ok_or(ResumeError::Api(ApiError::NotSelected(format!(
+            "{:?} is not in the watch history",
+            selection
+        ))))
+}
@@ -1,19 +1,20 @@
 use std::{collections::HashMap, error::Error, fmt};
 
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use indexmap::IndexMap;
 
 use crate::{
     api::{
         client::ApiClient,
-        models::response_models::{Link, Show, Shows, SourceUrl},
-        url_processor::{decrypt::decrypt_url, handle_source},
+        models::response_models::{Link, Show, Shows, SourceUrl, Subtitles},
+        url_processor::{decrypt::decrypt_url, handle_source, StreamHeaders},
     },
     app::App,
-    app_state::{AppState, CurrentEpisode, CurrentShow},
+    app_state::{AppState, CurrentEpisode, CurrentShow, ShowPick},
     cli::args::SearchCommand,
     config::{AudioMode, Quality},
-    menu::{error_menu, player_menu},
-    utils::fzf::{prompt_user, skim_menu},
+    episode::EpisodeNumber,
+    menu::{run, Screen},
 };
 
 #[derive(Debug)]
@@ -24,6 +25,7 @@ pub enum ApiError {
     BadUrl(String),
     MissingField(String),
     ClientError(String),
+    NotSelected(String),
 }
 
 impl fmt::Display for ApiError {
@@ -40,6 +42,7 @@ impl fmt::Display for ApiError {
                 "(҂◡_◡)",
                 format!("Client encountered an error: {}", message),
             ),
+            NotSelected(message) => ("(・_・ヾ", format!("Could not choose: {}", message)),
         };
 
         write!(f, "{} {}", emoji, desc)
@@ -48,73 +51,93 @@ impl fmt::Display for ApiError {
 
 impl Error for ApiError {}
 
-struct SearchResult {
-    show: CurrentShow,
-    episode: CurrentEpisode,
-}
-
 pub async fn search_command(app: &mut App, command: SearchCommand) {
     app.mut_state()
         .set_show_query(Some(command.title), command.episode);
-    search_then_menu(app).await;
+    app.mut_state().set_show_pick(command.selection.pick());
+    run(app, Screen::Search).await;
 }
 
-#[async_recursion::async_recursion]
-pub async fn search_then_menu(app: &mut App) {
-    match search(app).await {
-        Ok(result) => {
-            app.mut_state().set_show(result.show);
-            app.mut_state().set_episode(result.episode);
-
-            player_menu(app).await;
-        }
-        Err(e) => error_menu(app, e).await,
-    };
-}
-
-async fn search(app: &mut App) -> Result<SearchResult, ApiError> {
+/// Find the show and episode from the current query and make them current
+pub async fn search(app: &mut App) -> Result<(), ApiError> {
     let show = fetch_show(app.state(), app.client()).await?;
-    let episode_num = app.state().show_query().episode();
+    let episode_num = app.state().show_query().episode().cloned();
     let episode = fetch_episode(app.state(), app.client(), &show, episode_num).await?;
-    Ok(SearchResult { show, episode })
+    app.mut_state().set_show(show);
+    app.mut_state().set_episode(episode);
+    Ok(())
 }
 
 pub async fn fetch_show(state: &AppState, client: &ApiClient) -> Result<CurrentShow, ApiError> {
     let query = match state.show_query().title() {
         Some(query) => query.to_owned(),
-        None => enter_query(),
+        None if state.interactive() => match enter_query(state) {
+            query if query.trim().is_empty() => {
+                return Err(ApiError::NotSelected("no title entered".to_owned()))
+            }
+            query => query,
+        },
+        None => {
+            return Err(ApiError::NotSelected(
+                "a title is required in non-interactive mode".to_owned(),
+            ))
+        }
     };
-    let shows = search_shows(client, query).await?;
-    let show = select_show(state.audio_mode(), shows)?;
-    let available_episodes = search_episodes(client, show.id.clone(), state.audio_mode()).await?;
-
-    Ok(CurrentShow::new(show.id, show.name, available_episodes))
+    let shows = search_shows(client, query.clone()).await?;
+    let show = select_show(state, &query, shows)?;
+    let (available_episodes, mal_id) =
+        search_episodes(client, show.id.clone(), state.audio_mode()).await?;
+
+    Ok(CurrentShow::new(
+        show.id,
+        show.name,
+        mal_id,
+        available_episodes,
+    ))
 }
 
 pub async fn fetch_episode(
     state: &AppState,
     client: &ApiClient,
     show: &CurrentShow,
-    ep_number: Option<u32>,
+    ep_number: Option<EpisodeNumber>,
 ) -> Result<CurrentEpisode, ApiError> {
     let ep_number = match ep_number {
         Some(episode) => episode,
-        None => select_episode(show.available_episodes()),
+        None if state.interactive() => select_episode(state, show.available_episodes())?,
+        None => {
+            let available: Vec<&str> = show
+                .available_episodes()
+                .iter()
+                .map(|ep| ep.as_str())
+                .collect();
+            return Err(ApiError::NotSelected(format!(
+                "an episode is required in non-interactive mode, available: {}",
+                available.join(", ")
+            )));
+        }
     };
 
+    // Downloaded episodes play from disk without touching the network
+    if let Some(local) = state.local_episode(show, &ep_number) {
+        return Ok(local);
+    }
+
     let sources =
-        fetch_sources(client, show.id().to_owned(), state.audio_mode(), ep_number).await?;
+        fetch_sources(client, show.id().to_owned(), state.audio_mode(), &ep_number).await?;
     let source = select_source(state.known_providers(), &sources)?;
-    let url = fetch_url(client, state.quality(), source).await?;
+    let (url, headers, subtitles) = fetch_url(client, state.quality(), source).await?;
 
-    Ok(CurrentEpisode::new(ep_number, url))
+    let mut episode = CurrentEpisode::new(ep_number, url, headers);
+    episode.set_subtitles(subtitles);
+    Ok(episode)
 }
 
-fn enter_query() -> String {
-    prompt_user("Search: ")
+fn enter_query(state: &AppState) -> String {
+    state.picker().prompt("Search: ")
 }
 
-async fn search_shows(client: &ApiClient, query: String) -> Result<Shows, ApiError> {
+pub async fn search_shows(client: &ApiClient, query: String) -> Result<Shows, ApiError> {
     let response = client
         .request_shows(query.clone())
         .await
@@ -130,21 +153,91 @@ async fn search_shows(client: &ApiClient, query: String) -> Result<Shows, ApiErr
     }
 }
 
-fn select_show(audio_mode: &AudioMode, mut shows: Shows) -> Result<Show, ApiError> {
-    if shows.edges.len() > 1 {
-        let show_options = build_show_results(audio_mode, shows)?;
-        let display_strings: Vec<&str> = show_options.iter().map(|(s, _)| s.as_ref()).collect();
-        let selection = skim_menu(&display_strings, Some("Select show: "));
-        Ok(show_options
-            .into_iter()
-            .find_map(|(s, show)| if s == selection { Some(show) } else { None })
-            .expect("Selection from input collection"))
-    } else {
-        Ok(shows
+fn select_show(state: &AppState, query: &str, mut shows: Shows) -> Result<Show, ApiError> {
+    let pick = *state.show_query().pick();
+    if shows.edges.len() == 1 && matches!(pick, ShowPick::Prompt | ShowPick::First) {
+        return shows
             .edges
             .pop()
-            .ok_or(ApiError::MissingField("Show is empty".to_owned()))?)
+            .ok_or(ApiError::MissingField("Show is empty".to_owned()));
+    }
+
+    let show_options = build_show_results(state.audio_mode(), shows)?;
+    let index = match pick {
+        ShowPick::Index(n) => n
+            .checked_sub(1)
+            .filter(|&index| index < show_options.len())
+            .ok_or_else(|| {
+                not_selected(format!("--select {} is out of range", n), &show_options)
+            })?,
+        ShowPick::First => 0,
+        ShowPick::Exact => show_options
+            .iter()
+            .position(|(_, show)| {
+                show.name
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(query))
+            })
+            .ok_or_else(|| {
+                not_selected(
+                    format!("no show is named exactly {:?}", query),
+                    &show_options,
+                )
+            })?,
+        ShowPick::Prompt if state.interactive() => {
+            let display_strings: Vec<&str> = show_options.iter().map(|(s, _)| s.as_ref()).collect();
+            let selection = state
+                .picker()
+                .pick(&display_strings, Some("Select show: "))
+                .ok_or(ApiError::NotSelected("no show was selected".to_owned()))?;
+            // Launchers like dmenu let the user type text that isn't one of the options
+            show_options
+                .iter()
+                .position(|(s, _)| *s == selection)
+                .ok_or_else(|| {
+                    ApiError::NotSelected(format!("{:?} is not one of the shows", selection))
+                })?
+        }
+        ShowPick::Prompt => best_match(query, &show_options)
+            .ok_or_else(|| not_selected(format!("nothing matches {:?}", query), &show_options))?,
+    };
+    Ok(show_options
+        .into_iter()
+        .nth(index)
+        .expect("Index within results")
+        .1)
+}
+
+/// Index of the show whose name fuzzy matches `query` best, the earliest result on a tie
+fn best_match(query: &str, show_options: &[(String, Show)]) -> Option<usize> {
+    let matcher = SkimMatcherV2::default();
+    let mut best: Option<(usize, i64)> = None;
+    for (index, (_, show)) in show_options.iter().enumerate() {
+        let Some(score) = show
+            .name
+            .as_deref()
+            .and_then(|name| matcher.fuzzy_match(name, query))
+        else {
+            continue;
+        };
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((index, score));
+        }
     }
+    best.map(|(index, _)| index)
+}
+
+fn not_selected(reason: String, show_options: &[(String, Show)]) -> ApiError {
+    let candidates: Vec<String> = show_options
+        .iter()
+        .enumerate()
+        .map(|(index, (display, _))| format!("  {}. {}", index + 1, display))
+        .collect();
+    ApiError::NotSelected(format!(
+        "{}, candidates:\n{}",
+        reason,
+        candidates.join("\n")
+    ))
 }
 
 fn build_show_results(
@@ -179,54 +272,57 @@ fn build_show_results(
     }
 }
 
-async fn search_episodes(
+/// Episodes available in `audio_mode`, along with the show's MAL id when it has one
+pub async fn search_episodes(
     client: &ApiClient,
     show_id: String,
     audio_mode: &AudioMode,
-) -> Result<Vec<u32>, ApiError> {
+) -> Result<(Vec<EpisodeNumber>, Option<String>), ApiError> {
     let response = client
         .request_episodes(show_id)
         .await
         .map_err(|_| ApiError::ClientError("Failed to request episodes".to_owned()))?;
-    let episodes_details = response
-        .data
-        .show
-        .expect("No show in the response")
-        .available_episodes_detail
-        .expect("No episodes details");
+    let show = response.data.show.expect("No show in the response");
+    let episodes_details = show.available_episodes_detail.expect("No episodes details");
     let episode_vec = match audio_mode {
         AudioMode::Sub => episodes_details.sub,
         AudioMode::Dub => episodes_details.dub,
         AudioMode::Raw => episodes_details.raw,
     };
 
-    episode_vec
-        .iter()
-        .map(|x| {
-            x.parse().map_err(|_| {
-                ApiError::MissingField(format!("Could not parse episode number {}", x).to_owned())
-            })
-        })
-        .collect()
+    Ok((
+        episode_vec.into_iter().map(EpisodeNumber::new).collect(),
+        show.mal_id,
+    ))
 }
 
-pub fn select_episode(available_episodes: &[u32]) -> u32 {
-    let display_episodes: Vec<String> = available_episodes
+pub fn select_episode(
+    state: &AppState,
+    available_episodes: &[EpisodeNumber],
+) -> Result<EpisodeNumber, ApiError> {
+    let display_episodes: Vec<&str> = available_episodes
         .iter()
         .rev()
-        .map(|x| x.to_string())
+        .map(|x| x.as_str())
         .collect();
-    let display_episodes: Vec<&str> = display_episodes.iter().map(|x| x.as_str()).collect();
-    skim_menu(&display_episodes, Some("Select episode: "))
-        .parse()
-        .expect("Selecting from from previously parsed integers")
+    let selection = state
+        .picker()
+        .pick(&display_episodes, Some("Select episode: "))
+        .ok_or(ApiError::NotSelected("no episode was selected".to_owned()))?;
+    if !display_episodes.contains(&selection.as_str()) {
+        return Err(ApiError::NotSelected(format!(
+            "episode {:?} is not available",
+            selection
+        )));
+    }
+    Ok(EpisodeNumber::new(selection))
 }
 
-async fn fetch_sources(
+pub async fn fetch_sources(
     client: &ApiClient,
     show_id: String,
     audio_mode: &AudioMode,
-    episode: u32,
+    episode: &EpisodeNumber,
 ) -> Result<Vec<SourceUrl>, ApiError> {
     let response = client
         .request_streams(show_id, audio_mode, episode)
@@ -271,7 +367,7 @@ async fn fetch_url(
     client: &ApiClient,
     quality: &Quality,
     stream: SourceUrl,
-) -> Result<String, ApiError> {
+) -> Result<(String, StreamHeaders, Vec<Subtitles>), ApiError> {
     let decrypted_url =
         decrypt_url(stream.source_url).map_err(|e| ApiError::BadUrl(e.to_owned()))?;
 
@@ -293,15 +389,16 @@ async fn find_first_quality(
     client: &reqwest::Client,
     quality: &Quality,
     links: &[Link],
-) -> Option<String> {
-    for link in links.iter().map(|e| &e.link) {
-        let qualities = match handle_source(client, link).await {
+) -> Option<(String, StreamHeaders, Vec<Subtitles>)> {
+    for link in links {
+        let qualities = match handle_source(client, &link.link).await {
             Some(value) => value,
             None => continue,
         };
 
         let quality = select_quality(quality, &qualities);
-        return quality; // return the first occurrence
+        // return the first occurrence
+        return quality.map(|url| (url, StreamHeaders::global(), link.subtitles().to_vec()));
     }
 
     None // if no quality was found in any link
@@ -0,0 +1,51 @@
+use crate::{
+    app::App, app_state::CurrentEpisode, cli::args::UrlCommand, menu::error_menu,
+    utils::copy_to_clipboard,
+};
+
+use super::search::{fetch_episode, fetch_show};
+
+pub async fn url_command(app: &mut App, command: UrlCommand) {
+    // Never opens a picker, and a downloaded file is no use on another device
+    app.mut_state().set_interactive(false);
+    app.mut_state().set_force_stream(true);
+    app.mut_state()
+        .set_show_query(Some(command.title), Some(command.episode.clone()));
+    app.mut_state().set_show_pick(command.selection.pick());
+
+    let show = match fetch_show(app.state(), app.client()).await {
+        Ok(show) => show,
+        Err(e) => error_menu(app, e).await,
+    };
+    let episode = match fetch_episode(app.state(), app.client(), &show, Some(command.episode)).await
+    {
+        Ok(episode) => episode,
+        Err(e) => error_menu(app, e).await,
+    };
+
+    println!("{}", episode.url());
+    if command.verbose {
+        print_details(&episode);
+    }
+    if command.copy {
+        if let Err(e) = copy_to_clipboard(episode.url()) {
+            error_menu(app, e).await;
+        }
+    }
+}
+
+/// Headers as `Name: value` lines, then one line per subtitle track
+fn print_details(episode: &CurrentEpisode) {
+    for line in episode.headers().lines() {
+        println!("{}", line);
+    }
+    for subtitle in episode.subtitles() {
+        println!(
+            "Subtitle {} ({}){}: {}",
+            subtitle.lang,
+            subtitle.label,
+            if subtitle.default { " default" } else { "" },
+            subtitle.src
+        );
+    }
+}
@@ -1,12 +1,25 @@
 use std::{
-    fs,
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fmt, fs,
     io::{self},
+    path::{Path, PathBuf},
 };
 
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, EnumString};
 
-use crate::menu::options::MenuOption;
+use crate::{
+    downloader::DEFAULT_RPC_URL,
+    menu::options::MenuOption,
+    paths,
+    player::skip::{DEFAULT_API_URL, DEFAULT_KEY},
+    utils::is_command_available,
+};
+
+const ENV_PREFIX: &str = "ANIRUST_";
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -14,12 +27,35 @@ pub enum ConfigError {
     // FileNotFound(String),
     IoError(io::Error),
     ParseError(String),
+    SerializeError(String),
+    AlreadyExists(PathBuf),
+    EnvVar(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DirectoryNotFound => write!(f, "Configuration directory not found"),
+            Self::IoError(e) => write!(f, "Failed to access configuration: {}", e),
+            Self::ParseError(message) => write!(f, "{}", message),
+            Self::SerializeError(message) => write!(f, "Failed to save setting: {}", message),
+            Self::EnvVar(var, message) => write!(f, "{}: {}", var, message),
+            Self::AlreadyExists(path) => write!(
+                f,
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Config {
     state: Option<State>,
     pub player: Option<Player>,
+    download: Option<Download>,
+    hooks: Option<Hooks>,
+    skip: Option<Skip>,
 }
 
 impl Config {
@@ -31,13 +67,35 @@ impl Config {
         self.player.as_ref()
     }
 
-    pub fn load_configuration() -> Result<Self, ConfigError> {
-        let default_config = Config::default();
-        // Get the configuration directory for the current user
-        let mut config_path = dirs::home_dir().ok_or(ConfigError::DirectoryNotFound)?;
+    pub fn download(&self) -> Option<&Download> {
+        self.download.as_ref()
+    }
 
-        // Append the provided file name to the configuration directory
-        config_path.push(".config/anirust/config.toml");
+    pub fn hooks(&self) -> Option<&Hooks> {
+        self.hooks.as_ref()
+    }
+
+    pub fn skip(&self) -> Option<&Skip> {
+        self.skip.as_ref()
+    }
+
+    /// Config file to use, whether or not it exists
+    ///
+    /// `--config` wins over `ANIRUST_CONFIG`, which wins over `$XDG_CONFIG_HOME/anirust/config.toml`.
+    pub fn locate(explicit: Option<&Path>) -> Result<PathBuf, ConfigError> {
+        if let Some(path) = explicit {
+            return Ok(path.to_owned());
+        }
+        if let Some(path) = env::var_os("ANIRUST_CONFIG").filter(|path| !path.is_empty()) {
+            return Ok(PathBuf::from(path));
+        }
+        paths::config_dir()
+            .map(|dir| dir.join("config.toml"))
+            .ok_or(ConfigError::DirectoryNotFound)
+    }
+
+    pub fn load_configuration(config_path: &Path) -> Result<Self, ConfigError> {
+        let default_config = Config::default();
 
         // Ensure the config file exists
         if !config_path.exists() {
@@ -45,21 +103,436 @@ impl Config {
         }
 
         // Read the file
-        let contents = fs::read_to_string(&config_path).map_err(ConfigError::IoError)?;
+        let contents = fs::read_to_string(config_path).map_err(ConfigError::IoError)?;
 
         // Parse the TOML string into the Config struct
-        let config: Config = toml::from_str(&contents)
-            .map_err(|e| ConfigError::ParseError(format!("Failed to parse TOML: {}", e)))?;
+        let config: Config = toml::from_str(&contents).map_err(|e| {
+            ConfigError::ParseError(format!("Failed to parse {}: {}", config_path.display(), e))
+        })?;
 
         Ok(config)
     }
+
+    /// Values used for keys that aren't set anywhere
+    pub fn defaults() -> Self {
+        Config {
+            state: Some(State {
+                picker: Some(PickerKind::default()),
+                ..State::default()
+            }),
+            player: Some(Player {
+                media_player: Some(MediaPlayer::detect()),
+                wait: Some(false),
+                proxy: Some(false),
+                ..Player::default()
+            }),
+            download: Some(Download {
+                backend: Some(DownloadBackend::default()),
+                layout: Some(Layout::default()),
+                rpc_url: Some(DEFAULT_RPC_URL.to_owned()),
+                ..Download::default()
+            }),
+            hooks: None,
+            skip: Some(Skip {
+                enabled: Some(true),
+                auto: Some(false),
+                api_url: Some(DEFAULT_API_URL.to_owned()),
+                key: Some(DEFAULT_KEY.to_owned()),
+            }),
+        }
+    }
+
+    /// Config holding only the given sections, used as a layer over other sources
+    pub fn layer(state: State, player: Player) -> Self {
+        Config {
+            state: Some(state),
+            player: Some(player),
+            ..Config::default()
+        }
+    }
+
+    /// Keys set in `other` replace the same keys here, everything else is kept
+    pub fn merge(self, other: Config) -> Config {
+        let mut merged = toml::Table::try_from(self).expect("Config serializes to a table");
+        merge_tables(
+            &mut merged,
+            toml::Table::try_from(other).expect("Config serializes to a table"),
+        );
+        // Both sides came from a `Config` and only whole keys are replaced
+        toml::Value::Table(merged)
+            .try_into()
+            .expect("Merged config tables deserialize into a Config")
+    }
+
+    /// Unknown sections and keys, and values of the wrong type, in a config file
+    ///
+    /// Keys are checked one at a time so every mistake is reported, not just the first.
+    pub fn validate(contents: &str) -> Vec<Problem> {
+        let document: toml::Table = match contents.parse() {
+            Ok(document) => document,
+            Err(e) => {
+                return vec![Problem {
+                    line: None,
+                    message: e.to_string(),
+                }]
+            }
+        };
+
+        let mut problems = Vec::new();
+        for (section, table) in &document {
+            let known = matches!(Self::single(section, None), Ok(Some(_)));
+            let toml::Value::Table(table) = table else {
+                let message = if known {
+                    format!(
+                        "`{}` should be a section, found {}",
+                        section,
+                        table.type_str()
+                    )
+                } else {
+                    format!("unknown key `{}`", section)
+                };
+                problems.push(Problem {
+                    line: line_of(contents, None, section),
+                    message,
+                });
+                continue;
+            };
+            if !known {
+                problems.push(Problem {
+                    line: line_of(contents, None, section),
+                    message: format!("unknown section `[{}]`", section),
+                });
+                continue;
+            }
+            for (key, value) in table {
+                let line = line_of(contents, Some(section), key);
+                match Self::single(section, Some((key, value.clone()))) {
+                    Err(e) => problems.push(Problem {
+                        line,
+                        message: format!("`{}.{}`: {}", section, key, e.message()),
+                    }),
+                    Ok(None) => problems.push(Problem {
+                        line,
+                        message: format!("unknown key `{}.{}`", section, key),
+                    }),
+                    Ok(Some(_)) => (),
+                }
+            }
+        }
+        problems.sort_by_key(|problem| problem.line);
+        problems
+    }
+
+    /// Settings from `ANIRUST_<SECTION>_<KEY>` variables, like `ANIRUST_STATE_QUALITY=worst`
+    ///
+    /// Variables that don't name a setting, or hold a value of the wrong type, are skipped
+    /// and returned as errors.
+    pub fn from_env() -> (Config, Vec<ConfigError>) {
+        Self::from_vars(env::vars_os())
+    }
+
+    /// Variables that aren't valid UTF-8 are only an error when they belong to anirust
+    fn from_vars(
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> (Config, Vec<ConfigError>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        for (var, raw) in vars {
+            let Some(var) = var.to_str().filter(|var| var.starts_with(ENV_PREFIX)) else {
+                continue;
+            };
+            let name = &var[ENV_PREFIX.len()..];
+            // Picks the file, handled by `locate`
+            if name == "CONFIG" {
+                continue;
+            }
+            // Passed to download hooks, which may run anirust themselves
+            if name.starts_with("HOOK_") {
+                continue;
+            }
+            let Some(raw) = raw.to_str() else {
+                errors.push(ConfigError::EnvVar(
+                    var.to_owned(),
+                    "value is not valid UTF-8".to_owned(),
+                ));
+                continue;
+            };
+            let name = name.to_lowercase();
+            let result = match name.split_once('_') {
+                Some((section, key)) => Self::env_setting(section, key, raw),
+                None => Err("not a setting, expected ANIRUST_<SECTION>_<KEY>".to_owned()),
+            };
+            match result {
+                Ok(setting) => config = config.merge(setting),
+                Err(message) => errors.push(ConfigError::EnvVar(var.to_owned(), message)),
+            }
+        }
+        (config, errors)
+    }
+
+    /// Values are read as TOML when that gives the right type, otherwise as plain strings
+    fn env_setting(section: &str, key: &str, raw: &str) -> Result<Config, String> {
+        if !matches!(Self::single(section, None), Ok(Some(_))) {
+            return Err(format!("unknown section `{}`", section));
+        }
+        let literal = format!("value = {}", raw)
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        if let Some(Ok(Some(config))) =
+            literal.map(|value| Self::single(section, Some((key, value))))
+        {
+            return Ok(config);
+        }
+        match Self::single(section, Some((key, toml::Value::String(raw.to_owned())))) {
+            Ok(Some(config)) => Ok(config),
+            Ok(None) => Err(format!("unknown setting `{}.{}`", section, key)),
+            Err(e) => Err(format!("`{}.{}`: {}", section, key, e.message())),
+        }
+    }
+
+    /// Config holding only `section`, or only `key` in it
+    ///
+    /// `None` when the section or key isn't one anirust knows.
+    fn single(
+        section: &str,
+        entry: Option<(&str, toml::Value)>,
+    ) -> Result<Option<Config>, toml::de::Error> {
+        let key = entry.as_ref().map(|(key, _)| key.to_owned());
+        let table = toml::Table::from_iter(entry.map(|(key, value)| (key.to_owned(), value)));
+        let probe = toml::Table::from_iter([(section.to_owned(), toml::Value::Table(table))]);
+        let config: Config = toml::Value::Table(probe).try_into()?;
+        Ok(Self::keeps(&config, section, key).then_some(config))
+    }
+
+    /// Whether `section`, and `key` in it, were kept when deserialising into `config`
+    ///
+    /// Unknown sections and keys are dropped by serde, known ones come back out.
+    fn keeps(config: &Config, section: &str, key: Option<&str>) -> bool {
+        let round_trip = toml::Table::try_from(config).unwrap_or_default();
+        match (round_trip.get(section), key) {
+            (Some(toml::Value::Table(table)), Some(key)) => table.contains_key(key),
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+
+    /// Write `key` under `[section]` in the config file, creating the file if needed
+    ///
+    /// Comments, formatting and keys anirust doesn't know about are left as they are.
+    pub fn save_setting<T: Serialize>(
+        config_path: &Path,
+        section: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), ConfigError> {
+        let contents = match fs::read_to_string(config_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ConfigError::IoError(e)),
+        };
+        let contents = Self::with_setting(&contents, section, key, value)?;
+
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent).map_err(ConfigError::IoError)?;
+        }
+        fs::write(config_path, contents).map_err(ConfigError::IoError)
+    }
+
+    /// `contents` of a config file with `key` under `[section]` set to `value`
+    fn with_setting<T: Serialize>(
+        contents: &str,
+        section: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<String, ConfigError> {
+        let mut document = contents
+            .parse::<toml_edit::Document>()
+            .map_err(|e| ConfigError::ParseError(format!("Failed to parse TOML: {}", e)))?;
+
+        let value = toml::Value::try_from(value)
+            .map_err(|e| ConfigError::SerializeError(e.to_string()))?
+            .to_string()
+            .parse::<toml_edit::Value>()
+            .map_err(|e| ConfigError::SerializeError(e.to_string()))?;
+        let table = document
+            .entry(section)
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| ConfigError::SerializeError(format!("{} is not a table", section)))?;
+        match table.get_mut(key).and_then(|item| item.as_value_mut()) {
+            // Keep any comment trailing the old value
+            Some(existing) => {
+                let decor = existing.decor().clone();
+                *existing = value;
+                *existing.decor_mut() = decor;
+            }
+            None => table[key] = toml_edit::value(value),
+        }
+        Ok(document.to_string())
+    }
+}
+
+fn merge_tables(base: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(other)) => {
+                merge_tables(base, other)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Configuration from every source, each layer overriding the ones before it
+///
+/// The defaults come first, then the config file, the environment and the command line.
+#[derive(Debug, Default)]
+pub struct ConfigLayers {
+    layers: Vec<(Source, Config)>,
+}
+
+impl ConfigLayers {
+    pub fn push(&mut self, source: Source, config: Config) {
+        self.layers.push((source, config));
+    }
+
+    /// The settings in effect, without defaults, which each part of the app applies itself
+    pub fn merged(&self) -> Config {
+        self.layers
+            .iter()
+            .fold(Config::default(), |merged, (_, config)| {
+                merged.merge(config.clone())
+            })
+    }
+
+    /// Source of the value in effect for `key` under `[section]`, `None` when unset
+    pub fn source_of(&self, section: &str, key: &str) -> Option<Source> {
+        self.layers
+            .iter()
+            .rev()
+            .find(|(_, config)| Config::keeps(config, section, Some(key)))
+            .map(|(source, _)| *source)
+    }
+
+    /// Every key that has a value, sorted by section, with where the value came from
+    pub fn settings(&self) -> Vec<Setting> {
+        let defaults = Config::defaults();
+        let layers = std::iter::once((Source::Default, &defaults))
+            .chain(self.layers.iter().map(|(source, config)| (*source, config)));
+        let mut settings: IndexMap<(String, String), Setting> = IndexMap::new();
+        for (source, config) in layers {
+            for (section, table) in toml::Table::try_from(config).unwrap_or_default() {
+                let toml::Value::Table(table) = table else {
+                    continue;
+                };
+                for (key, value) in table {
+                    settings.insert(
+                        (section.clone(), key.clone()),
+                        Setting {
+                            section: section.clone(),
+                            key,
+                            value,
+                            source,
+                        },
+                    );
+                }
+            }
+        }
+        settings.sort_keys();
+        settings.into_values().collect()
+    }
+}
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File => write!(f, "config file"),
+            Self::Env => write!(f, "environment"),
+            Self::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// One key of the effective configuration
+#[derive(Debug)]
+pub struct Setting {
+    pub section: String,
+    pub key: String,
+    pub value: toml::Value,
+    pub source: Source,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// A mistake found by [`Config::validate`]
+#[derive(Debug)]
+pub struct Problem {
+    /// 1-based line in the file, when it could be located
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Line of `key` in `[section]`, or of the `[section.key]` header for nested tables
+fn line_of(contents: &str, section: Option<&str>, key: &str) -> Option<usize> {
+    let header = match section {
+        Some(section) => format!("{}.{}", section, key),
+        None => key.to_owned(),
+    };
+    let mut current: Option<&str> = None;
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let name = line
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or("")
+                .trim();
+            if name == header {
+                return Some(index + 1);
+            }
+            current = Some(name);
+        } else if current == section
+            && line.split('=').next().map(|k| k.trim().trim_matches('"')) == Some(key)
+            && line.contains('=')
+        {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct State {
     pub quality: Option<Quality>,
     pub audio_mode: Option<AudioMode>,
     pub download_dir: Option<String>,
+    /// Next/previous jump from the last episode to the first and back
+    pub wraparound: Option<bool>,
+    /// Menu used for every choice, defaults to the embedded skim
+    pub picker: Option<PickerKind>,
+    /// Source tried before the others, by name (`Sak`) or host (`dropbox`)
+    pub provider: Option<String>,
 }
 impl Default for State {
     fn default() -> Self {
@@ -67,24 +540,95 @@ impl Default for State {
             quality: Some(Quality::Best),
             audio_mode: Some(AudioMode::Sub),
             download_dir: Some("anime".to_owned()),
+            wraparound: Some(false),
+            picker: None,
+            provider: None,
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Player {
     pub media_player: Option<MediaPlayer>,
+    /// argv template for `media_player = "custom"`, e.g. `["celluloid", "{url}"]`
+    pub custom_command: Option<Vec<String>>,
+    /// Extra arguments per player, keyed by player name
+    pub extra_args: Option<HashMap<String, Vec<String>>>,
+    /// Wait for the player to exit before returning to the menu
+    pub wait: Option<bool>,
+    /// Serve streams through a local proxy that adds the provider's headers
+    pub proxy: Option<bool>,
 }
 
-impl Default for Player {
-    fn default() -> Self {
-        Player {
-            media_player: Some(MediaPlayer::IINA),
-        }
-    }
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Download {
+    pub backend: Option<DownloadBackend>,
+    pub layout: Option<Layout>,
+    pub rpc_url: Option<String>,
+    pub rpc_secret: Option<String>,
+    pub rpc_dir: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Deserialize, Clone, EnumString, AsRefStr, Default)]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum Layout {
+    /// `<show>/<show> Episode 5.mp4`
+    #[default]
+    #[serde(rename = "default")]
+    Default,
+    /// `<show>/Season 01/<show> - S01E05.mp4` with Kodi/Jellyfin `.nfo` metadata
+    #[serde(rename = "media-server")]
+    MediaServer,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Hooks {
+    pub on_episode_downloaded: Option<String>,
+    pub on_batch_complete: Option<String>,
+}
+
+/// Opening and ending skip times for mpv based players
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Skip {
+    /// Look up skip times at all, defaults to true
+    pub enabled: Option<bool>,
+    /// Seek past openings and endings without waiting for the key
+    pub auto: Option<bool>,
+    /// Base URL of an AniSkip compatible API
+    pub api_url: Option<String>,
+    /// mpv key that skips the current opening or ending
+    pub key: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum DownloadBackend {
+    /// Spawn a fresh aria2c for every episode
+    #[default]
+    #[serde(rename = "aria2c")]
+    Aria2c,
+    /// Submit episodes to a running aria2 daemon over JSON-RPC
+    #[serde(rename = "aria2-rpc")]
+    Aria2Rpc,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Copy, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PickerKind {
+    /// Embedded fuzzy finder
+    #[default]
+    Skim,
+    Fzf,
+    /// `rofi -dmenu`
+    Rofi,
+    Dmenu,
+    Bemenu,
+    /// Numbered list answered on stdin
+    Stdin,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(
+    Debug, PartialEq, Deserialize, Serialize, Clone, EnumString, AsRefStr, Default, clap::ValueEnum,
+)]
 pub enum MediaPlayer {
     #[default]
     #[serde(rename = "iina")]
@@ -93,6 +637,8 @@ pub enum MediaPlayer {
     MPV,
     #[serde(rename = "vlc")]
     VLC,
+    #[serde(rename = "custom")]
+    Custom,
 }
 
 impl MediaPlayer {
@@ -101,13 +647,27 @@ impl MediaPlayer {
             MediaPlayer::IINA => "iina",
             MediaPlayer::MPV => "mpv",
             MediaPlayer::VLC => "vlc",
+            MediaPlayer::Custom => "custom",
         }
     }
+
+    /// First built-in player found on the system, falling back to the default
+    pub fn detect() -> Self {
+        [MediaPlayer::IINA, MediaPlayer::MPV, MediaPlayer::VLC]
+            .into_iter()
+            .find(|player| is_command_available(player.as_str()))
+            .unwrap_or_default()
+    }
 }
 
 impl MenuOption for MediaPlayer {
     fn all_options() -> Vec<&'static str> {
-        vec![Self::IINA.as_ref(), Self::MPV.as_ref(), Self::VLC.as_ref()]
+        vec![
+            Self::IINA.as_ref(),
+            Self::MPV.as_ref(),
+            Self::VLC.as_ref(),
+            Self::Custom.as_ref(),
+        ]
     }
     fn from_str(s: &str) -> Option<Self> {
         s.parse().ok()
@@ -134,7 +694,18 @@ impl MenuOption for AudioMode {
     }
 }
 
-#[derive(Debug, PartialEq, Deserialize, Clone, Copy, EnumString, AsRefStr, Default)]
+#[derive(
+    Debug,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    EnumString,
+    AsRefStr,
+    Default,
+    clap::ValueEnum,
+)]
 pub enum Quality {
     #[default]
     #[serde(rename = "best")]
@@ -151,3 +722,227 @@ impl MenuOption for Quality {
         s.parse().ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        pairs
+            .iter()
+            .map(|(var, value)| (var.into(), value.into()))
+            .collect()
+    }
+
+    #[test]
+    fn saving_a_setting_keeps_comments_and_unknown_keys() {
+        let contents = "\
+# my settings
+[state]
+quality = \"best\" # for the big screen
+mystery = 1
+
+[custom]
+kept = true
+";
+        let saved = Config::with_setting(contents, "state", "quality", &Quality::Worst)
+            .expect("Setting saved");
+        assert_eq!(
+            saved,
+            "\
+# my settings
+[state]
+quality = \"worst\" # for the big screen
+mystery = 1
+
+[custom]
+kept = true
+"
+        );
+
+        let saved = Config::with_setting(&saved, "player", "media_player", &MediaPlayer::MPV)
+            .expect("Setting saved");
+        assert!(saved.starts_with("# my settings\n"));
+        assert!(saved.ends_with("[player]\nmedia_player = \"mpv\"\n"));
+    }
+
+    #[test]
+    fn saving_into_an_empty_file_creates_the_section() {
+        let saved = Config::with_setting("", "state", "audio_mode", &AudioMode::Dub)
+            .expect("Setting saved");
+        assert_eq!(saved, "[state]\naudio_mode = \"dub\"\n");
+    }
+
+    fn problems(contents: &str) -> Vec<String> {
+        Config::validate(contents)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn valid_file_has_no_problems() {
+        let contents = "[state]\nquality = \"worst\"\n\n[player.extra_args]\nmpv = [\"--fs\"]\n";
+        assert!(problems(contents).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_every_problem_with_its_line() {
+        let contents = "\
+# comment
+[state]
+qualty = \"best\"
+quality = \"bestt\"
+wraparound = true
+
+[mystery]
+x = 1
+";
+        assert_eq!(
+            problems(contents),
+            [
+                "line 3: unknown key `state.qualty`",
+                "line 4: `state.quality`: unknown variant `bestt`, expected `best` or `worst`",
+                "line 7: unknown section `[mystery]`",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_a_known_section_of_the_wrong_type() {
+        assert_eq!(
+            problems("state = 1\nfoo = 2\n"),
+            [
+                "line 1: `state` should be a section, found integer",
+                "line 2: unknown key `foo`",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_syntax_errors() {
+        let problems = problems("[state\n");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("line 1"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn line_of_finds_keys_within_their_section() {
+        let contents = "\
+quality = 1
+[player]
+quality = 2
+[state]
+  \"quality\" = 3
+[player.extra_args]
+";
+        assert_eq!(line_of(contents, None, "quality"), Some(1));
+        assert_eq!(line_of(contents, Some("state"), "quality"), Some(5));
+        assert_eq!(line_of(contents, Some("player"), "quality"), Some(3));
+        assert_eq!(line_of(contents, Some("player"), "extra_args"), Some(6));
+        assert_eq!(line_of(contents, None, "state"), Some(4));
+        assert_eq!(line_of(contents, Some("skip"), "key"), None);
+    }
+
+    #[test]
+    fn env_vars_set_typed_values() {
+        let (config, errors) = Config::from_vars(vars(&[
+            ("ANIRUST_STATE_QUALITY", "worst"),
+            ("ANIRUST_PLAYER_WAIT", "true"),
+            ("ANIRUST_PLAYER_MEDIA_PLAYER", "mpv"),
+            ("ANIRUST_CONFIG", "/elsewhere.toml"),
+            ("ANIRUST_HOOK_SHOW", "Show"),
+            ("ANIRUST_HOOK_EPISODE", "3"),
+            ("PATH", "/usr/bin"),
+        ]));
+        assert!(errors.is_empty(), "{:?}", errors);
+        let state = config.state().expect("state set");
+        assert_eq!(state.quality, Some(Quality::Worst));
+        let player = config.player().expect("player set");
+        assert_eq!(player.wait, Some(true));
+        assert_eq!(player.media_player, Some(MediaPlayer::MPV));
+    }
+
+    fn config(contents: &str) -> Config {
+        toml::from_str(contents).expect("Valid config")
+    }
+
+    fn layers() -> ConfigLayers {
+        let mut layers = ConfigLayers::default();
+        layers.push(
+            Source::File,
+            config(
+                "[state]\nquality = \"worst\"\naudio_mode = \"dub\"\ndownload_dir = \"file\"\n\n[player]\nwait = true\n",
+            ),
+        );
+        layers.push(
+            Source::Env,
+            config("[state]\naudio_mode = \"sub\"\ndownload_dir = \"env\"\n"),
+        );
+        layers.push(Source::Cli, config("[state]\ndownload_dir = \"cli\"\n"));
+        layers
+    }
+
+    #[test]
+    fn later_layers_override_only_the_keys_they_set() {
+        let merged = layers().merged();
+        let state = merged.state().expect("State section");
+        assert_eq!(state.quality, Some(Quality::Worst));
+        assert_eq!(state.audio_mode, Some(AudioMode::Sub));
+        assert_eq!(state.download_dir.as_deref(), Some("cli"));
+        // Untouched sections survive layers that don't mention them
+        assert_eq!(merged.player().and_then(|player| player.wait), Some(true));
+    }
+
+    #[test]
+    fn settings_name_the_layer_each_value_came_from() {
+        let layers = layers();
+        assert_eq!(layers.source_of("state", "quality"), Some(Source::File));
+        assert_eq!(layers.source_of("state", "audio_mode"), Some(Source::Env));
+        assert_eq!(layers.source_of("state", "download_dir"), Some(Source::Cli));
+        assert_eq!(layers.source_of("state", "provider"), None);
+
+        let source = |key: &str| {
+            layers
+                .settings()
+                .into_iter()
+                .find(|setting| setting.section == "download" && setting.key == key)
+                .map(|setting| setting.source)
+        };
+        // Keys no layer sets fall back to the defaults
+        assert_eq!(source("backend"), Some(Source::Default));
+    }
+
+    #[test]
+    fn env_var_errors_name_the_variable() {
+        let (_, errors) = Config::from_vars(vars(&[
+            ("ANIRUST_PLAYER_WAIT", "yes"),
+            ("ANIRUST_STATE_QUALTY", "best"),
+        ]));
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("ANIRUST_PLAYER_WAIT: "));
+        assert!(messages[1].starts_with("ANIRUST_STATE_QUALTY: unknown setting"));
+    }
+
+    #[test]
+    fn non_utf8_env_vars_do_not_abort() {
+        let junk = OsString::from_vec(vec![0xff]);
+        let (config, errors) = Config::from_vars([
+            (OsString::from("JUNK"), junk.clone()),
+            (
+                OsString::from_vec(b"\xffJUNK".to_vec()),
+                OsString::from("1"),
+            ),
+            (OsString::from("ANIRUST_STATE_DOWNLOAD_DIR"), junk),
+        ]);
+        assert!(config.state().is_none());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "ANIRUST_STATE_DOWNLOAD_DIR: value is not valid UTF-8"
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    api::url_processor::StreamHeaders,
+    config::{Config, DownloadBackend},
+    hooks::DownloadHooks,
+};
+
+pub const DEFAULT_RPC_URL: &str = "http://localhost:6800/jsonrpc";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct AppDownloader {
+    backend: DownloadBackend,
+    rpc: Aria2Rpc,
+    hooks: DownloadHooks,
+}
+
+impl AppDownloader {
+    pub fn backend(&self) -> &DownloadBackend {
+        &self.backend
+    }
+
+    pub fn rpc(&self) -> &Aria2Rpc {
+        &self.rpc
+    }
+
+    pub fn hooks(&self) -> &DownloadHooks {
+        &self.hooks
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let download = config.download().cloned().unwrap_or_default();
+        AppDownloader {
+            backend: download.backend.unwrap_or_default(),
+            rpc: Aria2Rpc {
+                client: reqwest::Client::new(),
+                url: download.rpc_url.unwrap_or(DEFAULT_RPC_URL.to_owned()),
+                secret: download.rpc_secret,
+                dir: download.rpc_dir,
+            },
+            hooks: DownloadHooks::from_config(config),
+        }
+    }
+}
+
+/// Client for an aria2 daemon started with `--enable-rpc`
+#[derive(Debug)]
+pub struct Aria2Rpc {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    dir: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadStatus {
+    status: String,
+    total_length: String,
+    completed_length: String,
+    download_speed: String,
+    error_message: Option<String>,
+}
+
+impl Aria2Rpc {
+    /// Queue `uri` on the daemon and wait until it has finished downloading
+    ///
+    /// The file keeps its path relative to `download_dir`, re-rooted at the configured
+    /// `rpc_dir` when the daemon does not share our filesystem.
+    pub async fn download(
+        &self,
+        uri: &str,
+        headers: &StreamHeaders,
+        download_dir: &Path,
+        output_path: &Path,
+    ) -> Result<(), String> {
+        let out = output_path
+            .file_name()
+            .expect("Episode path has a file name")
+            .to_string_lossy()
+            .into_owned();
+        let local_dir = output_path.parent().expect("Episode path has a parent");
+        let dir = match &self.dir {
+            Some(dir) => match local_dir.strip_prefix(download_dir) {
+                Ok(relative) => Path::new(dir).join(relative),
+                Err(_) => Path::new(dir).to_path_buf(),
+            },
+            None => local_dir.to_path_buf(),
+        };
+
+        let gid = self
+            .add_uri(uri, headers, &dir.to_string_lossy(), &out)
+            .await?;
+        self.wait_for(&gid, &out).await
+    }
+
+    async fn add_uri(
+        &self,
+        uri: &str,
+        headers: &StreamHeaders,
+        dir: &str,
+        out: &str,
+    ) -> Result<String, String> {
+        let options = json!({
+            "header": headers.lines(),
+            "dir": dir,
+            "out": out,
+            "continue": "true",
+            "check-certificate": "false",
+            "max-connection-per-server": "16",
+            "split": "16",
+        });
+        let result = self
+            .call("aria2.addUri", vec![json!([uri]), options])
+            .await?;
+        result
+            .as_str()
+            .map(|gid| gid.to_owned())
+            .ok_or(format!("Unexpected aria2.addUri result: {}", result))
+    }
+
+    async fn tell_status(&self, gid: &str) -> Result<DownloadStatus, String> {
+        let keys = json!([
+            "status",
+            "totalLength",
+            "completedLength",
+            "downloadSpeed",
+            "errorMessage"
+        ]);
+        let result = self
+            .call("aria2.tellStatus", vec![json!(gid), keys])
+            .await?;
+        serde_json::from_value(result).map_err(|e| format!("Unexpected aria2 status: {}", e))
+    }
+
+    async fn wait_for(&self, gid: &str, out: &str) -> Result<(), String> {
+        loop {
+            let status = self.tell_status(gid).await?;
+            match status.status.as_str() {
+                "complete" => {
+                    eprintln!("\r{}: download complete", out);
+                    return Ok(());
+                }
+                "error" => {
+                    eprintln!();
+                    return Err(status
+                        .error_message
+                        .unwrap_or("aria2 reported an error".to_owned()));
+                }
+                "removed" => {
+                    eprintln!();
+                    return Err(format!("{} was removed from aria2", out));
+                }
+                _ => print_progress(out, &status),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn call(&self, method: &str, mut params: Vec<Value>) -> Result<Value, String> {
+        if let Some(secret) = &self.secret {
+            params.insert(0, json!(format!("token:{}", secret)));
+        }
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": "anirust",
+            "method": method,
+            "params": params,
+        });
+
+        let response: RpcResponse = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| format!("Could not reach aria2 at {}: {}", self.url, e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid response from aria2: {}", e))?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(format!("aria2 error {}: {}", error.code, error.message)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(format!("Empty response from aria2 for {}", method)),
+        }
+    }
+}
+
+fn print_progress(out: &str, status: &DownloadStatus) {
+    let total: u64 = status.total_length.parse().unwrap_or(0);
+    let completed: u64 = status.completed_length.parse().unwrap_or(0);
+    let speed: u64 = status.download_speed.parse().unwrap_or(0);
+    let percent = if total > 0 {
+        completed as f64 * 100.0 / total as f64
+    } else {
+        0.0
+    };
+    eprint!(
+        "\r{}: {} {:.1}% ({:.1} MiB/s)   ",
+        out,
+        status.status,
+        percent,
+        speed as f64 / (1024.0 * 1024.0)
+    );
+    let _ = io::stderr().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+
+    /// aria2 stand-in answering one JSON-RPC call per connection with the next scripted reply
+    ///
+    /// Resolves to the requests it received.
+    async fn stub(replies: Vec<Value>) -> (String, JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Stub bound");
+        let url = format!("http://{}/jsonrpc", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let (mut socket, _) = listener.accept().await.expect("Connection");
+                requests.push(read_request(&mut socket).await);
+                let body = reply.to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> Value {
+        let mut buffer = Vec::new();
+        loop {
+            let mut chunk = [0; 4096];
+            let read = socket.read(&mut chunk).await.expect("Request read");
+            buffer.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&buffer);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return serde_json::from_str(body).expect("JSON-RPC request");
+                }
+            }
+            assert!(read > 0, "Connection closed mid request");
+        }
+    }
+
+    fn rpc(url: String, secret: Option<&str>, dir: Option<&str>) -> Aria2Rpc {
+        Aria2Rpc {
+            client: reqwest::Client::new(),
+            url,
+            secret: secret.map(str::to_owned),
+            dir: dir.map(str::to_owned),
+        }
+    }
+
+    fn status(status: &str, error_message: Option<&str>) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": "anirust",
+            "result": {
+                "status": status,
+                "totalLength": "100",
+                "completedLength": "100",
+                "downloadSpeed": "0",
+                "errorMessage": error_message,
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn download_queues_the_uri_and_waits_for_completion() {
+        let (url, requests) = stub(vec![
+            json!({"jsonrpc": "2.0", "id": "anirust", "result": "2089b05ecca3d829"}),
+            status("complete", None),
+        ])
+        .await;
+        let headers = StreamHeaders::global();
+        rpc(url, Some("hunter2"), Some("/srv/anime"))
+            .download(
+                "https://example.com/ep1.mp4",
+                &headers,
+                Path::new("/home/user/anime"),
+                Path::new("/home/user/anime/Show/Show Episode 1.mp4"),
+            )
+            .await
+            .expect("Download complete");
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests[0]["method"], "aria2.addUri");
+        let params = requests[0]["params"].as_array().unwrap();
+        assert_eq!(params[0], "token:hunter2");
+        assert_eq!(params[1], json!(["https://example.com/ep1.mp4"]));
+        // The local layout is re-rooted at the daemon's directory
+        assert_eq!(params[2]["dir"], "/srv/anime/Show");
+        assert_eq!(params[2]["out"], "Show Episode 1.mp4");
+        assert_eq!(params[2]["header"], json!(headers.lines()));
+
+        assert_eq!(requests[1]["method"], "aria2.tellStatus");
+        assert_eq!(requests[1]["params"][1], "2089b05ecca3d829");
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_are_reported() {
+        let (url, _) = stub(vec![json!({
+            "jsonrpc": "2.0",
+            "id": "anirust",
+            "error": {"code": 1, "message": "Unauthorized"}
+        })])
+        .await;
+        let error = rpc(url, None, None)
+            .download(
+                "https://example.com/ep1.mp4",
+                &StreamHeaders::default(),
+                Path::new("/anime"),
+                Path::new("/anime/Show/Show Episode 1.mp4"),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error, "aria2 error 1: Unauthorized");
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_report_aria2s_message() {
+        let (url, requests) = stub(vec![
+            json!({"jsonrpc": "2.0", "id": "anirust", "result": "gid"}),
+            status("error", Some("Resource not found")),
+        ])
+        .await;
+        let error = rpc(url, None, None)
+            .download(
+                "https://example.com/ep1.mp4",
+                &StreamHeaders::default(),
+                Path::new("/anime"),
+                Path::new("/anime/Show/Show Episode 1.mp4"),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error, "Resource not found");
+
+        // Without rpc_dir the local directory is used, and no token is sent
+        let requests = requests.await.unwrap();
+        assert_eq!(
+            requests[0]["params"][0],
+            json!(["https://example.com/ep1.mp4"])
+        );
+        assert_eq!(requests[0]["params"][1]["dir"], "/anime/Show");
+    }
+}
@@ -0,0 +1,150 @@
+use std::{cmp::Ordering, convert::Infallible, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Episode identifier as the API spells it, e.g. `"0"`, `"12"` or `"12.5"` for a recap
+///
+/// Episodes order numerically, with identifiers that aren't numbers sorted after them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct EpisodeNumber(String);
+
+impl EpisodeNumber {
+    pub fn new(episode: impl Into<String>) -> Self {
+        EpisodeNumber(episode.into().trim().to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// `f64` parsing also accepts `inf` and `NaN`, which aren't episode numbers
+    fn value(&self) -> Option<f64> {
+        self.0.parse().ok().filter(|value: &f64| value.is_finite())
+    }
+
+    /// Whole part of a numeric episode: `12` for both `12` and `12.5`
+    pub fn whole(&self) -> Option<u64> {
+        self.value()
+            .filter(|value| *value >= 0.0)
+            .map(|value| value.trunc() as u64)
+    }
+
+    /// Zero padded to `width` digits before any decimal part: `5` -> `05`, `12.5` -> `12.5`
+    pub fn padded(&self, width: usize) -> String {
+        match self.0.split_once('.') {
+            Some((whole, fraction)) => format!("{:0>width$}.{}", whole, fraction),
+            None => format!("{:0>width$}", self.0),
+        }
+    }
+}
+
+impl Ord for EpisodeNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.value(), other.value()) {
+            (Some(a), Some(b)) => a.total_cmp(&b).then_with(|| self.0.cmp(&other.0)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => self.0.cmp(&other.0),
+        }
+    }
+}
+
+impl PartialOrd for EpisodeNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for EpisodeNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for EpisodeNumber {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(EpisodeNumber::new(s))
+    }
+}
+
+// Watch history written before episodes were strings stores them as numbers
+impl<'de> Deserialize<'de> for EpisodeNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Number(serde_json::Number),
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Text(text) => EpisodeNumber::new(text),
+            Raw::Number(number) => EpisodeNumber::new(number.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(episodes: &[&str]) -> Vec<String> {
+        let mut episodes: Vec<EpisodeNumber> =
+            episodes.iter().copied().map(EpisodeNumber::new).collect();
+        episodes.sort();
+        episodes.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn numbers_order_numerically() {
+        assert_eq!(
+            sorted(&["10", "2", "5.5", "5", "0", "6"]),
+            ["0", "2", "5", "5.5", "6", "10"]
+        );
+    }
+
+    #[test]
+    fn non_numeric_episodes_come_last() {
+        assert_eq!(
+            sorted(&["special", "3", "OVA", "nan", "1", "inf"]),
+            ["1", "3", "OVA", "inf", "nan", "special"]
+        );
+    }
+
+    #[test]
+    fn equal_values_fall_back_to_the_text() {
+        assert_eq!(sorted(&["5.0", "05", "5"]), ["05", "5", "5.0"]);
+        assert_ne!(EpisodeNumber::new("5"), EpisodeNumber::new("5.0"));
+        assert_eq!(EpisodeNumber::new(" 5 "), EpisodeNumber::new("5"));
+    }
+
+    #[test]
+    fn padding_only_applies_to_the_whole_part() {
+        assert_eq!(EpisodeNumber::new("5").padded(2), "05");
+        assert_eq!(EpisodeNumber::new("5.5").padded(2), "05.5");
+        assert_eq!(EpisodeNumber::new("12.5").padded(2), "12.5");
+        assert_eq!(EpisodeNumber::new("123").padded(2), "123");
+    }
+
+    #[test]
+    fn whole_parts_drop_the_fraction() {
+        assert_eq!(EpisodeNumber::new("12").whole(), Some(12));
+        assert_eq!(EpisodeNumber::new("12.5").whole(), Some(12));
+        assert_eq!(EpisodeNumber::new("0").whole(), Some(0));
+        assert_eq!(EpisodeNumber::new("-1").whole(), None);
+        assert_eq!(EpisodeNumber::new("OVA").whole(), None);
+    }
+
+    #[test]
+    fn history_numbers_deserialize() {
+        let episodes: Vec<EpisodeNumber> =
+            serde_json::from_str(r#"[3, 4.5, "6"]"#).expect("Valid episodes");
+        assert_eq!(
+            sorted(&["3", "4.5", "6"]),
+            episodes.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+    }
+}
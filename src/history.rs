@@ -0,0 +1,263 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::AudioMode, episode::EpisodeNumber, paths, player::ipc::Progress};
+
+/// Serialises read-modify-write cycles between the menu and background progress trackers
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug)]
+pub enum HistoryError {
+    DirectoryNotFound,
+    IoError(io::Error),
+    ParseError(String),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DirectoryNotFound => write!(f, "Data directory not found"),
+            Self::IoError(e) => write!(f, "Failed to access watch history: {}", e),
+            Self::ParseError(message) => write!(f, "Failed to parse watch history: {}", message),
+        }
+    }
+}
+
+impl Error for HistoryError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub show_id: String,
+    pub name: String,
+    pub audio_mode: AudioMode,
+    pub episode: EpisodeNumber,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub position: Option<f64>,
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        show_id: String,
+        name: String,
+        audio_mode: AudioMode,
+        episode: EpisodeNumber,
+    ) -> Self {
+        HistoryEntry {
+            show_id,
+            name,
+            audio_mode,
+            episode,
+            timestamp: now(),
+            position: None,
+            duration: None,
+            finished: false,
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: &Progress) {
+        self.position = Some(progress.position);
+        self.duration = progress.duration;
+        self.finished = progress.is_finished();
+    }
+
+    /// Part of the name, ignoring case, or the exact show id
+    pub fn matches(&self, filter: &str) -> bool {
+        self.show_id == filter || self.name.to_lowercase().contains(&filter.to_lowercase())
+    }
+}
+
+/// Shows watched in previous sessions, most recent first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn path() -> Result<PathBuf, HistoryError> {
+        let dir = paths::data_dir().ok_or(HistoryError::DirectoryNotFound)?;
+        Ok(dir.join("history.json"))
+    }
+
+    pub fn load() -> Result<Self, HistoryError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(History::default());
+        }
+        let contents = fs::read_to_string(&path).map_err(HistoryError::IoError)?;
+        serde_json::from_str(&contents).map_err(|e| HistoryError::ParseError(e.to_string()))
+    }
+
+    pub fn save(&self) -> Result<(), HistoryError> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(HistoryError::IoError)?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| HistoryError::ParseError(e.to_string()))?;
+        fs::write(&path, contents).map_err(HistoryError::IoError)
+    }
+
+    /// Apply `change` to the stored history and write it back
+    pub fn update(change: impl FnOnce(&mut History)) -> Result<(), HistoryError> {
+        let _guard = HISTORY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut history = Self::load()?;
+        change(&mut history);
+        history.save()
+    }
+
+    /// Record the episode being watched, replacing the show's previous entry
+    ///
+    /// Progress already stored for the same episode is kept when `entry` has none.
+    pub fn record(&mut self, mut entry: HistoryEntry) {
+        if entry.position.is_none() {
+            if let Some(previous) = self.entries.iter().find(|e| {
+                e.show_id == entry.show_id
+                    && e.audio_mode == entry.audio_mode
+                    && e.episode == entry.episode
+            }) {
+                entry.position = previous.position;
+                entry.duration = previous.duration;
+                entry.finished = previous.finished;
+            }
+        }
+        self.entries
+            .retain(|e| !(e.show_id == entry.show_id && e.audio_mode == entry.audio_mode));
+        self.entries.insert(0, entry);
+    }
+
+    /// Store the playback position reported by the player for an episode
+    pub fn record_progress(
+        &mut self,
+        show_id: &str,
+        audio_mode: AudioMode,
+        episode: &EpisodeNumber,
+        progress: &Progress,
+    ) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.show_id == show_id && e.audio_mode == audio_mode && &e.episode == episode)
+        {
+            entry.set_progress(progress);
+            entry.timestamp = now();
+        }
+    }
+
+    /// Delete entries matching `filter`, returning how many were removed
+    pub fn remove(&mut self, filter: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| !e.matches(filter));
+        before - self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(audio_mode: AudioMode, episode: &str) -> HistoryEntry {
+        HistoryEntry::new(
+            "show".to_owned(),
+            "Show".to_owned(),
+            audio_mode,
+            EpisodeNumber::new(episode),
+        )
+    }
+
+    fn halfway() -> Progress {
+        Progress {
+            position: 720.0,
+            duration: Some(1440.0),
+            eof_reached: false,
+        }
+    }
+
+    #[test]
+    fn recording_replaces_the_entry_for_the_same_audio_mode() {
+        let mut history = History::default();
+        history.record(entry(AudioMode::Sub, "1"));
+        history.record(entry(AudioMode::Dub, "1"));
+        history.record(entry(AudioMode::Sub, "2"));
+        let entries: Vec<(AudioMode, &str)> = history
+            .entries()
+            .iter()
+            .map(|e| (e.audio_mode, e.episode.as_str()))
+            .collect();
+        assert_eq!(entries, [(AudioMode::Sub, "2"), (AudioMode::Dub, "1")]);
+    }
+
+    #[test]
+    fn recording_the_same_episode_keeps_its_progress() {
+        let mut history = History::default();
+        history.record(entry(AudioMode::Sub, "1"));
+        history.record_progress("show", AudioMode::Sub, &EpisodeNumber::new("1"), &halfway());
+        history.record(entry(AudioMode::Sub, "1"));
+        assert_eq!(history.entries()[0].position, Some(720.0));
+
+        // A new episode starts from the beginning
+        history.record(entry(AudioMode::Sub, "2"));
+        assert_eq!(history.entries()[0].position, None);
+    }
+
+    #[test]
+    fn progress_is_stored_for_the_matching_audio_mode_only() {
+        let mut history = History::default();
+        history.record(entry(AudioMode::Sub, "1"));
+        history.record(entry(AudioMode::Dub, "1"));
+        history.record_progress("show", AudioMode::Dub, &EpisodeNumber::new("1"), &halfway());
+        let position = |history: &History, audio_mode| {
+            history
+                .entries()
+                .iter()
+                .find(|e| e.audio_mode == audio_mode)
+                .and_then(|e| e.position)
+        };
+        assert_eq!(position(&history, AudioMode::Dub), Some(720.0));
+        assert_eq!(position(&history, AudioMode::Sub), None);
+
+        // Progress for an episode that isn't the show's current one is dropped
+        history.record_progress("show", AudioMode::Sub, &EpisodeNumber::new("5"), &halfway());
+        assert_eq!(position(&history, AudioMode::Sub), None);
+    }
+
+    #[test]
+    fn entries_match_by_name_or_exact_id() {
+        let entry = HistoryEntry::new(
+            "ReooPAxPMsHM4KPMY".to_owned(),
+            "One Piece".to_owned(),
+            AudioMode::Sub,
+            EpisodeNumber::new("1"),
+        );
+        assert!(entry.matches("piece"));
+        assert!(entry.matches("ONE P"));
+        assert!(entry.matches("ReooPAxPMsHM4KPMY"));
+        assert!(!entry.matches("reoopaxpmshm4kpmy"));
+        assert!(!entry.matches("bleach"));
+    }
+}
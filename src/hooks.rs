@@ -0,0 +1,154 @@
+use std::{path::Path, process::Command};
+
+use crate::{config::Config, episode::EpisodeNumber};
+
+/// Shell commands run after downloads finish
+///
+/// Hooks are run with `sh -c` and receive details about the download through
+/// `ANIRUST_HOOK_*` environment variables. A failing hook is reported but never aborts the download.
+#[derive(Debug, Default)]
+pub struct DownloadHooks {
+    on_episode_downloaded: Option<String>,
+    on_batch_complete: Option<String>,
+}
+
+impl DownloadHooks {
+    pub fn from_config(config: &Config) -> Self {
+        let hooks = config.hooks().cloned().unwrap_or_default();
+        DownloadHooks {
+            on_episode_downloaded: hooks.on_episode_downloaded,
+            on_batch_complete: hooks.on_batch_complete,
+        }
+    }
+
+    pub fn episode_downloaded(
+        &self,
+        file: &Path,
+        show_name: &str,
+        show_id: &str,
+        episode: &EpisodeNumber,
+    ) {
+        if let Some(command) = &self.on_episode_downloaded {
+            run_hook(
+                "on_episode_downloaded",
+                command,
+                &episode_env(file, show_name, show_id, episode),
+            );
+        }
+    }
+
+    pub fn batch_complete(
+        &self,
+        show_dir: &Path,
+        show_name: &str,
+        show_id: &str,
+        episodes: &[EpisodeNumber],
+    ) {
+        if let Some(command) = &self.on_batch_complete {
+            run_hook(
+                "on_batch_complete",
+                command,
+                &batch_env(show_dir, show_name, show_id, episodes),
+            );
+        }
+    }
+}
+
+fn episode_env(
+    file: &Path,
+    show_name: &str,
+    show_id: &str,
+    episode: &EpisodeNumber,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("ANIRUST_HOOK_FILE", file.to_string_lossy().into_owned()),
+        ("ANIRUST_HOOK_SHOW", show_name.to_owned()),
+        ("ANIRUST_HOOK_SHOW_ID", show_id.to_owned()),
+        ("ANIRUST_HOOK_EPISODE", episode.to_string()),
+    ]
+}
+
+fn batch_env(
+    show_dir: &Path,
+    show_name: &str,
+    show_id: &str,
+    episodes: &[EpisodeNumber],
+) -> Vec<(&'static str, String)> {
+    let episodes: Vec<&str> = episodes.iter().map(|ep| ep.as_str()).collect();
+    vec![
+        ("ANIRUST_HOOK_DIR", show_dir.to_string_lossy().into_owned()),
+        ("ANIRUST_HOOK_SHOW", show_name.to_owned()),
+        ("ANIRUST_HOOK_SHOW_ID", show_id.to_owned()),
+        ("ANIRUST_HOOK_EPISODES", episodes.join(",")),
+    ]
+}
+
+fn run_hook(name: &str, command: &str, envs: &[(&str, String)]) {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(envs.iter().map(|(var, value)| (var, value)))
+        .status();
+    match status {
+        Ok(status) if status.success() => (),
+        Ok(status) => eprintln!("Hook `{}` failed: {}", name, status),
+        Err(e) => eprintln!("Hook `{}` could not be run: {}", name, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn episode_hooks_get_the_file_and_episode() {
+        assert_eq!(
+            episode_env(
+                Path::new("/anime/Show/Show Episode 12.5.mp4"),
+                "Show",
+                "abc",
+                &EpisodeNumber::new("12.5"),
+            ),
+            [
+                (
+                    "ANIRUST_HOOK_FILE",
+                    "/anime/Show/Show Episode 12.5.mp4".to_owned()
+                ),
+                ("ANIRUST_HOOK_SHOW", "Show".to_owned()),
+                ("ANIRUST_HOOK_SHOW_ID", "abc".to_owned()),
+                ("ANIRUST_HOOK_EPISODE", "12.5".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_hooks_get_the_episodes_comma_separated() {
+        let episodes = ["1", "2", "2.5"].map(EpisodeNumber::new);
+        let env = batch_env(Path::new("/anime/Show"), "Show", "abc", &episodes);
+        assert_eq!(env[0], ("ANIRUST_HOOK_DIR", "/anime/Show".to_owned()));
+        assert_eq!(env[3], ("ANIRUST_HOOK_EPISODES", "1,2,2.5".to_owned()));
+    }
+
+    #[test]
+    fn hooks_run_in_a_shell_with_their_variables() {
+        let output: PathBuf =
+            std::env::temp_dir().join(format!("anirust-test-hook-{}", std::process::id()));
+        let hooks = DownloadHooks {
+            on_episode_downloaded: None,
+            on_batch_complete: Some(format!(
+                "printf '%s %s' \"$ANIRUST_HOOK_SHOW\" \"$ANIRUST_HOOK_EPISODES\" > '{}'",
+                output.display()
+            )),
+        };
+        hooks.batch_complete(
+            Path::new("/anime/Show"),
+            "Show & Co",
+            "abc",
+            &[EpisodeNumber::new("3"), EpisodeNumber::new("5")],
+        );
+        assert_eq!(fs::read_to_string(&output).unwrap(), "Show & Co 3,5");
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -3,7 +3,13 @@ mod app;
 mod app_state;
 mod cli;
 mod config;
+mod downloader;
+mod episode;
+mod history;
+mod hooks;
 mod menu;
+mod nfo;
+mod paths;
 mod player;
 mod utils;
 
@@ -11,21 +17,44 @@ use app::App;
 use clap::Parser;
 use cli::{
     args::{AnirustArgs, ModeType},
-    commands::{download::download_command, search::search_command},
+    commands::{
+        config::config_command, download::download_command, history::history_command,
+        playlist::playlist_command, query::query_command, resume::continue_command,
+        search::search_command, url::url_command,
+    },
 };
-use menu::main_menu;
+use menu::{error_menu, run, Screen};
 
 #[tokio::main]
 async fn main() {
     // Parse user input
     let args = AnirustArgs::parse();
-    let mut app = match App::initialise_app().await {
+    let mut app = match App::initialise_app(args.config.as_deref(), args.overrides()).await {
         Ok(app) => app,
         Err(e) => panic!("{}", e),
     };
+    app.mut_state().set_force_stream(args.stream);
+    app.mut_player().set_binge(args.binge);
+    if args.non_interactive {
+        app.mut_state().set_interactive(false);
+        // Nothing is left to keep the proxy and progress tracking alive once anirust exits
+        app.mut_player().set_wait(true);
+    }
+    // Check if the media player is available before searching for something to play
+    if args.mode_type.plays() {
+        if let Err(error) = app.player().is_available().await {
+            error_menu(&mut app, error).await;
+        }
+    }
     match args.mode_type {
-        ModeType::Menu => main_menu(&mut app).await,
+        ModeType::Menu => run(&mut app, Screen::Main).await,
         ModeType::Search(command) => search_command(&mut app, command).await,
+        ModeType::Continue => continue_command(&mut app).await,
         ModeType::Download(command) => download_command(&mut app, command).await,
+        ModeType::Playlist(command) => playlist_command(&mut app, command).await,
+        ModeType::History(command) => history_command(command),
+        ModeType::Query(command) => query_command(&mut app, command).await,
+        ModeType::Url(command) => url_command(&mut app, command).await,
+        ModeType::Config(command) => config_command(app.config_path(), app.config(), command),
     }
 }
@@ -1,74 +1,157 @@
+use std::error::Error;
+
+use serde::Serialize;
+
 use crate::{
     app::App,
     cli::commands::{
         download::download,
-        search::{fetch_episode, search_then_menu, select_episode, ApiError},
+        resume::resume,
+        search::{fetch_episode, search, select_episode, ApiError},
     },
+    config::{AudioMode, Config, ConfigError, MediaPlayer, Quality, Source},
+    history::{History, HistoryEntry},
 };
 
 use super::{
-    audio_menu, error_menu, main_menu, media_player_menu,
-    options::{ErrorOption, MainOption, PlayerOption, SettingOption},
-    player_menu, quality_menu, settings_menu,
+    options::{ErrorOption, MainOption, PlayerOption, SaveOption, SettingOption},
+    Screen, Transition,
 };
 
-pub async fn handle_main_option(app: &mut App, option: MainOption) {
+pub fn handle_main_option(app: &mut App, option: MainOption) -> Transition {
     match option {
-        MainOption::Search => search_show_handler(app).await,
-        MainOption::Settings => settings_handler(app).await,
-        MainOption::Quit => quit_handler(),
+        MainOption::Continue => Transition::Push(Screen::Continue),
+        MainOption::Search => {
+            app.mut_state().set_show_query(None, None);
+            Transition::Push(Screen::Search)
+        }
+        MainOption::Settings => Transition::Push(Screen::Settings),
+        MainOption::Quit => Transition::Quit,
     }
 }
 
-async fn search_show_handler(app: &mut App) {
-    app.mut_state().set_show_query(None, None);
-    search_then_menu(app).await;
+/// Find the show and episode asked for, then hand over to the player menu
+///
+/// Non-interactive commands play the episode and finish instead.
+pub async fn search_handler(app: &mut App) -> Transition {
+    match search(app).await {
+        Ok(_) if app.state().interactive() => Transition::Replace(Screen::Player),
+        Ok(_) => play_then_quit(app).await,
+        // A dismissed picker or empty query returns to where the search started
+        Err(ApiError::NotSelected(_)) if app.state().interactive() => Transition::Back,
+        Err(e) => Transition::Replace(Screen::Error(e.to_string())),
+    }
 }
 
-async fn settings_handler(app: &mut App) {
-    settings_menu(app).await;
+/// Resume a show from the watch history and start playing it straight away
+pub async fn continue_handler(app: &mut App) -> Transition {
+    if let Err(e) = resume(app).await {
+        return Transition::Replace(Screen::Error(e.to_string()));
+    }
+    if !app.state().interactive() {
+        return play_then_quit(app).await;
+    }
+    match play(app).await {
+        Ok(_) => Transition::Replace(Screen::Player),
+        Err(e) => Transition::Replace(Screen::Error(e.to_string())),
+    }
 }
 
-fn quit_handler() {
-    std::process::exit(0);
+async fn play_then_quit(app: &mut App) -> Transition {
+    match play(app).await {
+        Ok(_) => Transition::Quit,
+        Err(e) => Transition::Replace(Screen::Error(e.to_string())),
+    }
 }
 
-#[async_recursion::async_recursion]
-pub async fn handle_player_option(app: &mut App, option: PlayerOption) {
-    match option {
-        PlayerOption::Play => play_handler(app).await,
-        PlayerOption::Next => match next_handler(app).await {
-            Ok(_) => (),
-            Err(e) => error_menu(app, e).await,
-        },
-        PlayerOption::Previous => match previous_handler(app).await {
-            Ok(_) => (),
-            Err(e) => error_menu(app, e).await,
-        },
-        PlayerOption::Download => download_handler(app).await,
+/// Errors open on top of the player menu, so going back returns to the same episode
+pub async fn handle_player_option(app: &mut App, option: PlayerOption) -> Transition {
+    let result = match option {
+        PlayerOption::Play => play(app).await,
+        PlayerOption::Next => next_handler(app).await.map_err(Into::into),
+        PlayerOption::Previous => previous_handler(app).await.map_err(Into::into),
+        PlayerOption::Binge => {
+            binge_toggle_handler(app);
+            Ok(())
+        }
+        PlayerOption::Download => download(app).await.map(|_| ()).map_err(Into::into),
         PlayerOption::Select => match select_handler(app).await {
-            Ok(_) => (),
-            Err(e) => error_menu(app, e).await,
+            Err(ApiError::NotSelected(_)) => Ok(()),
+            result => result.map_err(Into::into),
         },
-        PlayerOption::Menu => {
-            main_menu(app).await;
-        }
-        PlayerOption::Quit => quit_handler(),
+        PlayerOption::Back => return Transition::Back,
+        PlayerOption::Menu => return Transition::Home,
+        PlayerOption::Quit => return Transition::Quit,
+    };
+    match result {
+        Ok(_) => Transition::Stay,
+        Err(e) => Transition::Push(Screen::Error(e.to_string())),
     }
 }
 
-#[async_recursion::async_recursion]
-async fn play_handler(app: &mut App) {
-    if let Err(e) = app.player().is_available().await {
-        error_menu(app, e).await
+pub async fn play(app: &mut App) -> Result<(), Box<dyn Error>> {
+    app.player().is_available().await?;
+    app.mut_state().prefer_local_episode();
+    record_history(app);
+    if app.player().binge() {
+        binge(app).await?;
+    } else {
+        let status = app.player().play(app.state()).await?;
+        app.mut_player().set_last_exit(status);
     }
-    app.player().play(app.state());
-    player_menu(app).await;
+    Ok(())
+}
+
+/// Play episodes back to back for as long as each one is watched to the end
+///
+/// The next episode's stream is resolved while the current one is playing.
+async fn binge(app: &mut App) -> Result<(), ApiError> {
+    loop {
+        let show = app.state().current_show().expect("Show selected");
+        let ep_number = app
+            .state()
+            .current_episode()
+            .expect("Episode selected")
+            .ep_number();
+        let prefetch = async {
+            match show.episode_after(ep_number) {
+                Some(next) => {
+                    Some(fetch_episode(app.state(), app.client(), show, Some(next.clone())).await)
+                }
+                None => None,
+            }
+        };
+        let (status, next_episode) = tokio::join!(app.player().play(app.state()), prefetch);
+        let status =
+            status.map_err(|e| ApiError::ClientError(format!("Failed to start player: {}", e)))?;
+
+        // Without progress from the player, a clean exit counts as finishing the episode
+        let completed = match app.player().progress(show.id(), ep_number) {
+            Some(progress) => progress.is_finished(),
+            None => status.is_some_and(|status| status.success()),
+        };
+        app.mut_player().set_last_exit(status);
+
+        match next_episode {
+            Some(next_episode) if completed => {
+                app.mut_state().set_episode(next_episode?);
+                app.mut_state().prefer_local_episode();
+                record_history(app);
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn binge_toggle_handler(app: &mut App) {
+    let binge = !app.player().binge();
+    app.mut_player().set_binge(binge);
 }
 
-#[async_recursion::async_recursion]
 async fn next_handler(app: &mut App) -> Result<(), ApiError> {
-    let next = app.state().next_episode();
+    let next = app.state().next_episode().ok_or(ApiError::NoEpisodes(
+        "Already at the last episode".to_owned(),
+    ))?;
     let current_episode = match app.state().current_show() {
         Some(current_show) => {
             fetch_episode(app.state(), app.client(), current_show, Some(next)).await?
@@ -80,13 +163,14 @@ async fn next_handler(app: &mut App) -> Result<(), ApiError> {
         }
     };
     app.mut_state().set_episode(current_episode);
-    player_menu(app).await;
+    record_history(app);
     Ok(())
 }
 
-#[async_recursion::async_recursion]
 async fn previous_handler(app: &mut App) -> Result<(), ApiError> {
-    let prev = app.state().previous_episode();
+    let prev = app.state().previous_episode().ok_or(ApiError::NoEpisodes(
+        "Already at the first episode".to_owned(),
+    ))?;
     let current_episode = match app.state().current_show() {
         Some(current_show) => {
             fetch_episode(app.state(), app.client(), current_show, Some(prev)).await?
@@ -98,23 +182,34 @@ async fn previous_handler(app: &mut App) -> Result<(), ApiError> {
         }
     };
     app.mut_state().set_episode(current_episode);
-    player_menu(app).await;
+    record_history(app);
     Ok(())
 }
 
-async fn download_handler(app: &mut App) {
-    match download(app) {
-        Ok(_) => player_menu(app).await,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-        }
+/// Remember the current show and episode so the session can be picked up later
+fn record_history(app: &App) {
+    let (Some(show), Some(episode)) = (app.state().current_show(), app.state().current_episode())
+    else {
+        return;
+    };
+    let mut entry = HistoryEntry::new(
+        show.id().to_owned(),
+        show.name().unwrap_or(show.id()).to_owned(),
+        *app.state().audio_mode(),
+        episode.ep_number().clone(),
+    );
+    if let Some(progress) = app.player().progress(show.id(), episode.ep_number()) {
+        entry.set_progress(&progress);
+    }
+    if let Err(e) = History::update(|history| history.record(entry)) {
+        eprintln!("Warning: {}", e);
     }
 }
 
 async fn select_handler(app: &mut App) -> Result<(), ApiError> {
     let current_episode = match app.state().current_show() {
         Some(current_show) => {
-            let ep_number = select_episode(current_show.available_episodes());
+            let ep_number = select_episode(app.state(), current_show.available_episodes())?;
             fetch_episode(app.state(), app.client(), current_show, Some(ep_number)).await?
         }
         None => {
@@ -124,39 +219,78 @@ async fn select_handler(app: &mut App) -> Result<(), ApiError> {
         }
     };
     app.mut_state().set_episode(current_episode);
-    player_menu(app).await;
 
     Ok(())
 }
 
-pub async fn handle_setting_option(app: &mut App, option: SettingOption) {
+pub fn handle_setting_option(option: SettingOption) -> Transition {
     match option {
-        SettingOption::Audio => audio_handler(app).await,
-        SettingOption::Quality => quality_handler(app).await,
-        SettingOption::Player => player_handler(app).await,
+        SettingOption::Audio => Transition::Push(Screen::Audio),
+        SettingOption::Quality => Transition::Push(Screen::Quality),
+        SettingOption::Player => Transition::Push(Screen::MediaPlayer),
+        SettingOption::Back => Transition::Back,
     }
 }
 
-#[async_recursion::async_recursion]
-pub async fn quality_handler(app: &mut App) {
-    quality_menu(app).await;
+pub fn handle_quality(app: &mut App, quality: Quality) -> Transition {
+    app.mut_state().set_quality(quality);
+    offer_to_save("state", "quality", &quality)
 }
 
-#[async_recursion::async_recursion]
-pub async fn audio_handler(app: &mut App) {
-    audio_menu(app).await;
+pub fn handle_audio(app: &mut App, audio_mode: AudioMode) -> Transition {
+    app.mut_state().set_audio_mode(audio_mode);
+    offer_to_save("state", "audio_mode", &audio_mode)
 }
 
-#[async_recursion::async_recursion]
-pub async fn player_handler(app: &mut App) {
-    media_player_menu(app).await;
+pub fn handle_media_player(app: &mut App, media_player: MediaPlayer) -> Transition {
+    let transition = offer_to_save("player", "media_player", &media_player);
+    app.mut_player().set_media_player(media_player);
+    transition
 }
 
-pub async fn handle_error_option(app: &mut App, option: ErrorOption) {
+/// Ask whether a setting changed in the menu should also apply to future runs
+fn offer_to_save<T: Serialize>(section: &'static str, key: &'static str, value: &T) -> Transition {
+    match toml::Value::try_from(value) {
+        Ok(value) => Transition::Replace(Screen::SaveSetting {
+            section,
+            key,
+            value,
+        }),
+        Err(_) => Transition::Back,
+    }
+}
+
+pub fn handle_save_option(
+    app: &App,
+    option: SaveOption,
+    section: &str,
+    key: &str,
+    value: &toml::Value,
+) -> Transition {
+    if let SaveOption::No = option {
+        return Transition::Back;
+    }
+    let saved = app
+        .config_path()
+        .ok_or(ConfigError::DirectoryNotFound)
+        .and_then(|path| Config::save_setting(path, section, key, value));
+    if let Err(e) = saved {
+        return Transition::Replace(Screen::Error(e.to_string()));
+    }
+    // The saved value is only used once nothing with higher precedence sets it
+    if let Some(source @ (Source::Env | Source::Cli)) = app.config().source_of(section, key) {
+        eprintln!(
+            "Warning: {}.{} saved, but the {} still overrides it",
+            section, key, source
+        );
+    }
+    Transition::Back
+}
+
+pub fn handle_error_option(option: ErrorOption) -> Transition {
     match option {
-        ErrorOption::Menu => {
-            main_menu(app).await;
-        }
-        ErrorOption::Quit => quit_handler(),
+        ErrorOption::Back => Transition::Back,
+        ErrorOption::Menu => Transition::Home,
+        ErrorOption::Quit => Transition::Quit,
     }
 }
@@ -2,59 +2,207 @@ use std::error::Error;
 
 use crate::{
     app::App,
-    app_state::AppState,
     config::{AudioMode, MediaPlayer, Quality},
-    utils::fzf::skim_menu,
+    player::ipc::Progress,
+    utils::{format_position, picker::Picker},
 };
 
 use self::{
     handlers::{
-        handle_error_option, handle_main_option, handle_player_option, handle_setting_option,
+        continue_handler, handle_audio, handle_error_option, handle_main_option,
+        handle_media_player, handle_player_option, handle_quality, handle_save_option,
+        handle_setting_option, search_handler,
     },
-    options::{ErrorOption, MainOption, MenuOption, PlayerOption, SettingOption},
+    options::{ErrorOption, MainOption, MenuOption, PlayerOption, SaveOption, SettingOption},
 };
 
 pub mod handlers;
 pub mod options;
 
-async fn generic_menu<T: MenuOption>(prompt: Option<&str>) -> Option<T> {
-    let options = T::all_options();
-    let selection_str = skim_menu(&options, prompt);
-    T::from_str(&selection_str)
+/// A place in the menu system
+#[derive(Debug, Clone, PartialEq)]
+pub enum Screen {
+    Main,
+    /// Search for a show and episode, then open the player menu
+    Search,
+    /// Pick up a show from the watch history and play it
+    Continue,
+    Player,
+    Settings,
+    Quality,
+    Audio,
+    MediaPlayer,
+    /// Offer to write a setting just changed in the menu to the config file
+    SaveSetting {
+        section: &'static str,
+        key: &'static str,
+        value: toml::Value,
+    },
+    Error(String),
 }
 
-pub async fn main_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<MainOption>(None).await {
-        handle_main_option(app, selection).await;
+impl Screen {
+    /// Screens that only show a menu, unlike the search and error screens which can run
+    /// without one
+    fn needs_choice(&self) -> bool {
+        !matches!(self, Screen::Search | Screen::Continue | Screen::Error(_))
     }
 }
 
-pub async fn player_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<PlayerOption>(Some(&player_prompt(app.state()))).await {
-        handle_player_option(app, selection).await;
+/// Where to go once a screen is done
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transition {
+    /// Open a screen on top of the current one
+    Push(Screen),
+    /// Swap the current screen for another
+    Replace(Screen),
+    /// Show the current screen again
+    Stay,
+    /// Return to the previous screen, leaving the menu from the first one
+    Back,
+    /// Start over from the main menu
+    Home,
+    Quit,
+}
+
+/// Show screens starting from `start` until the user quits or backs out of the first one
+pub async fn run(app: &mut App, start: Screen) {
+    let picker = app.state().picker();
+    run_with(app, picker.as_ref(), start).await
+}
+
+/// [`run`] with the menus shown by `picker` instead of the configured one
+pub async fn run_with(app: &mut App, picker: &dyn Picker, start: Screen) {
+    let mut stack = vec![start];
+    while let Some(screen) = stack.last().cloned() {
+        match show(app, picker, &screen).await {
+            Transition::Push(next) => stack.push(next),
+            Transition::Replace(next) => {
+                stack.pop();
+                stack.push(next);
+            }
+            Transition::Stay => (),
+            Transition::Back => {
+                stack.pop();
+            }
+            Transition::Home => {
+                stack.clear();
+                stack.push(Screen::Main);
+            }
+            Transition::Quit => break,
+        }
+    }
+}
+
+/// Dismissing a picker counts as going back
+async fn show(app: &mut App, picker: &dyn Picker, screen: &Screen) -> Transition {
+    // Nobody is there to choose, so non-interactive runs end with an error instead
+    if screen.needs_choice() && !app.state().interactive() {
+        return Transition::Replace(Screen::Error(
+            "a menu choice is required in non-interactive mode".to_owned(),
+        ));
+    }
+    match screen {
+        Screen::Main => match generic_menu::<MainOption>(picker, None) {
+            Some(option) => handle_main_option(app, option),
+            None => Transition::Back,
+        },
+        Screen::Search => search_handler(app).await,
+        Screen::Continue => continue_handler(app).await,
+        Screen::Player => match generic_menu::<PlayerOption>(picker, Some(&player_prompt(app))) {
+            Some(option) => handle_player_option(app, option).await,
+            None => Transition::Back,
+        },
+        Screen::Settings => {
+            match generic_menu::<SettingOption>(picker, Some(&settings_prompt(app))) {
+                Some(option) => handle_setting_option(option),
+                None => Transition::Back,
+            }
+        }
+        Screen::Quality => match generic_menu::<Quality>(picker, None) {
+            Some(quality) => handle_quality(app, quality),
+            None => Transition::Back,
+        },
+        Screen::Audio => match generic_menu::<AudioMode>(picker, None) {
+            Some(audio_mode) => handle_audio(app, audio_mode),
+            None => Transition::Back,
+        },
+        Screen::MediaPlayer => match generic_menu::<MediaPlayer>(picker, None) {
+            Some(media_player) => handle_media_player(app, media_player),
+            None => Transition::Back,
+        },
+        Screen::SaveSetting {
+            section,
+            key,
+            value,
+        } => match generic_menu::<SaveOption>(picker, Some("Save as default? ")) {
+            Some(option) => handle_save_option(app, option, section, key, value),
+            None => Transition::Back,
+        },
+        Screen::Error(message) => {
+            // Without a menu to return to, the error is reported and anirust exits with failure
+            if !app.state().interactive() {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+            match generic_menu::<ErrorOption>(picker, Some(message)) {
+                Some(option) => handle_error_option(option),
+                None => Transition::Back,
+            }
+        }
     }
 }
 
-fn player_prompt(state: &AppState) -> String {
+fn generic_menu<T: MenuOption>(picker: &dyn Picker, prompt: Option<&str>) -> Option<T> {
+    let options = T::all_options();
+    let selection_str = picker.pick(&options, prompt)?;
+    T::from_str(&selection_str)
+}
+
+fn player_prompt(app: &App) -> String {
+    let state = app.state();
+    let current_episode = state.current_episode().expect("Episode selected");
     format!(
-        "{:?} Episode: {:?} ({:?})  quality: {:?} ",
+        "{:?} Episode: {} ({:?})  quality: {:?} {}{}{}{}",
         state
             .current_show()
             .expect("Show selected")
             .name()
             .expect("Show has name"),
-        state
-            .current_episode()
-            .expect("Episode selected")
-            .ep_number(),
+        current_episode.ep_number(),
         state.audio_mode(),
         state.quality(),
+        if current_episode.is_local() {
+            "[local] "
+        } else {
+            ""
+        },
+        app.player()
+            .progress(
+                state.current_show().expect("Show selected").id(),
+                current_episode.ep_number(),
+            )
+            .map(|progress| format!("[{}] ", progress_label(&progress)))
+            .unwrap_or_default(),
+        if app.player().binge() { "[binge] " } else { "" },
+        app.player()
+            .last_exit()
+            .map(|status| format!("[player {}] ", status))
+            .unwrap_or_default(),
     )
 }
 
-pub async fn settings_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<SettingOption>(Some(&settings_prompt(app))).await {
-        handle_setting_option(app, selection).await;
+fn progress_label(progress: &Progress) -> String {
+    if progress.is_finished() {
+        return "watched".to_owned();
+    }
+    match progress.duration {
+        Some(duration) => format!(
+            "{}/{}",
+            format_position(progress.position),
+            format_position(duration)
+        ),
+        None => format_position(progress.position),
     }
 }
 
@@ -67,30 +215,173 @@ fn settings_prompt(app: &App) -> String {
     )
 }
 
-pub async fn quality_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<Quality>(None).await {
-        app.mut_state().set_quality(selection);
-        main_menu(app).await;
-    }
+/// Report an error from a command, offering the menu when interactive
+///
+/// Never returns: anirust exits with failure once the user quits or backs out of the error.
+pub async fn error_menu(app: &mut App, error: impl Error) -> ! {
+    run(app, Screen::Error(error.to_string())).await;
+    std::process::exit(1);
 }
 
-pub async fn audio_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<AudioMode>(None).await {
-        app.mut_state().set_audio_mode(selection);
-        main_menu(app).await;
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, path::PathBuf};
+
+    use crate::config::ConfigLayers;
+
+    use super::*;
+
+    /// Answers menus from a script, `None` standing for a dismissed picker
+    struct Scripted {
+        answers: RefCell<VecDeque<Option<&'static str>>>,
     }
-}
 
-pub async fn media_player_menu(app: &mut App) {
-    if let Some(selection) = generic_menu::<MediaPlayer>(None).await {
-        app.mut_player().set_media_player(selection);
-        main_menu(app).await;
+    impl Scripted {
+        fn new(answers: &[Option<&'static str>]) -> Self {
+            Scripted {
+                answers: RefCell::new(answers.iter().copied().collect()),
+            }
+        }
+
+        fn finished(&self) -> bool {
+            self.answers.borrow().is_empty()
+        }
+    }
+
+    impl Picker for Scripted {
+        fn pick(&self, options: &[&str], _prompt: Option<&str>) -> Option<String> {
+            let answer = self
+                .answers
+                .borrow_mut()
+                .pop_front()
+                .expect("Menu shown more often than scripted");
+            if let Some(answer) = answer {
+                assert!(options.contains(&answer), "{} not in {:?}", answer, options);
+            }
+            answer.map(str::to_owned)
+        }
+
+        fn prompt(&self, _prompt: &str) -> String {
+            String::new()
+        }
+    }
+
+    fn app() -> App {
+        App::from_layers(
+            Some(PathBuf::from("/nonexistent/config.toml")),
+            ConfigLayers::default(),
+        )
+        .expect("App from defaults")
+    }
+
+    #[test]
+    fn setting_options_open_their_screens() {
+        assert_eq!(
+            handle_setting_option(SettingOption::Audio),
+            Transition::Push(Screen::Audio)
+        );
+        assert_eq!(
+            handle_setting_option(SettingOption::Player),
+            Transition::Push(Screen::MediaPlayer)
+        );
+        assert_eq!(
+            handle_setting_option(SettingOption::Quality),
+            Transition::Push(Screen::Quality)
+        );
+        assert_eq!(handle_setting_option(SettingOption::Back), Transition::Back);
+    }
+
+    #[test]
+    fn error_options_leave_the_error() {
+        assert_eq!(handle_error_option(ErrorOption::Back), Transition::Back);
+        assert_eq!(handle_error_option(ErrorOption::Menu), Transition::Home);
+        assert_eq!(handle_error_option(ErrorOption::Quit), Transition::Quit);
+    }
+
+    #[test]
+    fn main_search_starts_a_fresh_query() {
+        let mut app = app();
+        app.mut_state()
+            .set_show_query(Some("berserk".to_owned()), None);
+        assert_eq!(
+            handle_main_option(&mut app, MainOption::Search),
+            Transition::Push(Screen::Search)
+        );
+        assert!(app.state().show_query().title().is_none());
+        assert_eq!(
+            handle_main_option(&mut app, MainOption::Quit),
+            Transition::Quit
+        );
+    }
+
+    #[tokio::test]
+    async fn dismissing_a_menu_goes_back() {
+        let mut app = app();
+        let picker = Scripted::new(&[None]);
+        assert_eq!(
+            show(&mut app, &picker, &Screen::Settings).await,
+            Transition::Back
+        );
+    }
+
+    #[tokio::test]
+    async fn menus_are_not_shown_when_non_interactive() {
+        let mut app = app();
+        app.mut_state().set_interactive(false);
+        let picker = Scripted::new(&[]);
+        for screen in [
+            Screen::Main,
+            Screen::Player,
+            Screen::Settings,
+            Screen::Quality,
+        ] {
+            assert!(matches!(
+                show(&mut app, &picker, &screen).await,
+                Transition::Replace(Screen::Error(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn back_returns_to_the_previous_screen() {
+        let mut app = app();
+        // Main -> Settings, escape back to Main, then quit
+        let picker = Scripted::new(&[Some("Settings"), None, Some("Quit")]);
+        run_with(&mut app, &picker, Screen::Main).await;
+        assert!(picker.finished());
+    }
+
+    #[tokio::test]
+    async fn changed_settings_offer_to_save_then_go_back() {
+        let mut app = app();
+        // Settings -> Quality, choose worst, don't save, then leave settings
+        let picker = Scripted::new(&[Some("Quality"), Some("Worst"), Some("No"), None]);
+        run_with(&mut app, &picker, Screen::Settings).await;
+        assert!(picker.finished());
+        assert_eq!(*app.state().quality(), Quality::Worst);
     }
-}
 
-pub async fn error_menu(app: &mut App, error: impl Error) {
-    let prompt = format!("{}", error);
-    if let Some(selection) = generic_menu::<ErrorOption>(Some(&prompt)).await {
-        handle_error_option(app, selection).await;
+    #[test]
+    fn changing_a_setting_leads_to_the_save_screen() {
+        let mut app = app();
+        assert_eq!(
+            handle_audio(&mut app, AudioMode::Dub),
+            Transition::Replace(Screen::SaveSetting {
+                section: "state",
+                key: "audio_mode",
+                value: toml::Value::String("dub".to_owned()),
+            })
+        );
+        assert_eq!(*app.state().audio_mode(), AudioMode::Dub);
+        assert_eq!(
+            handle_save_option(
+                &app,
+                SaveOption::No,
+                "state",
+                "audio_mode",
+                &toml::Value::String("dub".to_owned())
+            ),
+            Transition::Back
+        );
     }
 }
@@ -25,16 +25,19 @@ pub trait MenuOption: Sized {
     fn from_str(s: &str) -> Option<Self>;
 }
 
-impl_menu_option!(MainOption, Search, Settings, Quit);
+impl_menu_option!(MainOption, Continue, Search, Settings, Quit);
 impl_menu_option!(
     PlayerOption,
     Play,
     Next,
     Previous,
+    Binge,
     Download,
     Select,
+    Back,
     Menu,
     Quit
 );
-impl_menu_option!(SettingOption, Audio, Player, Quality);
-impl_menu_option!(ErrorOption, Menu, Quit);
+impl_menu_option!(SettingOption, Audio, Player, Quality, Back);
+impl_menu_option!(ErrorOption, Back, Menu, Quit);
+impl_menu_option!(SaveOption, No, Yes);
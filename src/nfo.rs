@@ -0,0 +1,143 @@
+use std::{fs, io, path::Path};
+
+use crate::{api::models::response_models::Show, episode::EpisodeNumber};
+
+/// Write `tvshow.nfo` for Kodi/Jellyfin from the show details returned by the API
+pub fn write_show_nfo(show_dir: &Path, show: &Show) -> io::Result<()> {
+    fs::write(show_dir.join("tvshow.nfo"), show_nfo(show))
+}
+
+/// Write the `.nfo` sidecar next to a downloaded episode
+pub fn write_episode_nfo(
+    episode_path: &Path,
+    show_name: &str,
+    episode: &EpisodeNumber,
+) -> io::Result<()> {
+    fs::write(
+        episode_path.with_extension("nfo"),
+        episode_nfo(show_name, episode),
+    )
+}
+
+fn show_nfo(show: &Show) -> String {
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n");
+    nfo.push_str("<tvshow>\n");
+    push_element(&mut nfo, "title", show.name.as_deref());
+    push_element(&mut nfo, "originaltitle", show.native_name.as_deref());
+    push_element(&mut nfo, "sorttitle", show.english_name.as_deref());
+    push_element(&mut nfo, "plot", show.description.as_deref());
+    push_element(&mut nfo, "status", show.status.as_deref());
+    if let Some(score) = show.score {
+        push_element(&mut nfo, "rating", Some(&score.to_string()));
+    }
+    if let Some(aired) = show.aired_start {
+        if let Some(year) = aired.year {
+            push_element(&mut nfo, "year", Some(&year.to_string()));
+            if let (Some(month), Some(date)) = (aired.month, aired.date) {
+                // The API counts months from zero
+                let premiered = format!("{:04}-{:02}-{:02}", year, month + 1, date);
+                push_element(&mut nfo, "premiered", Some(&premiered));
+            }
+        }
+    }
+    for genre in show.genres.iter().flatten() {
+        push_element(&mut nfo, "genre", Some(genre));
+    }
+    if let Some(thumbnail) = &show.thumbnail {
+        nfo.push_str(&format!(
+            "  <thumb aspect=\"poster\">{}</thumb>\n",
+            escape(thumbnail)
+        ));
+    }
+    nfo.push_str(&format!(
+        "  <uniqueid type=\"allanime\" default=\"true\">{}</uniqueid>\n",
+        escape(&show.id)
+    ));
+    nfo.push_str("</tvshow>\n");
+    nfo
+}
+
+/// Media servers only take whole episode numbers, so `12.5` is filed as episode 12 and
+/// keeps its number in the title
+fn episode_nfo(show_name: &str, episode: &EpisodeNumber) -> String {
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n");
+    nfo.push_str("<episodedetails>\n");
+    push_element(&mut nfo, "title", Some(&format!("Episode {}", episode)));
+    push_element(&mut nfo, "showtitle", Some(show_name));
+    push_element(&mut nfo, "season", Some("1"));
+    if let Some(whole) = episode.whole() {
+        push_element(&mut nfo, "episode", Some(&whole.to_string()));
+    }
+    nfo.push_str("</episodedetails>\n");
+    nfo
+}
+
+fn push_element(nfo: &mut String, tag: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        nfo.push_str(&format!("  <{0}>{1}</{0}>\n", tag, escape(value)));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(json: serde_json::Value) -> Show {
+        serde_json::from_value(json).expect("Valid show")
+    }
+
+    #[test]
+    fn titles_and_plots_are_escaped() {
+        let nfo = show_nfo(&show(serde_json::json!({
+            "_id": "abc",
+            "name": "Tom & Jerry <Remastered>",
+            "description": "\"Cats\" & 'mice' <br>",
+        })));
+        assert!(nfo.contains("  <title>Tom &amp; Jerry &lt;Remastered&gt;</title>\n"));
+        assert!(nfo.contains("  <plot>&quot;Cats&quot; &amp; &apos;mice&apos; &lt;br&gt;</plot>\n"));
+
+        let nfo = episode_nfo("Tom & Jerry", &EpisodeNumber::new("1"));
+        assert!(nfo.contains("  <showtitle>Tom &amp; Jerry</showtitle>\n"));
+    }
+
+    #[test]
+    fn premiered_dates_count_months_from_one() {
+        let nfo = show_nfo(&show(serde_json::json!({
+            "_id": "abc",
+            "airedStart": {"year": 2006, "month": 0, "date": 5},
+        })));
+        assert!(nfo.contains("  <year>2006</year>\n"));
+        assert!(nfo.contains("  <premiered>2006-01-05</premiered>\n"));
+
+        let nfo = show_nfo(&show(serde_json::json!({
+            "_id": "abc",
+            "airedStart": {"year": 2006, "month": 11},
+        })));
+        assert!(nfo.contains("  <year>2006</year>\n"));
+        assert!(!nfo.contains("<premiered>"));
+    }
+
+    #[test]
+    fn fractional_episodes_keep_their_number_in_the_title() {
+        let nfo = episode_nfo("Show", &EpisodeNumber::new("12.5"));
+        assert!(nfo.contains("  <title>Episode 12.5</title>\n"));
+        assert!(nfo.contains("  <episode>12</episode>\n"));
+
+        let nfo = episode_nfo("Show", &EpisodeNumber::new("3"));
+        assert!(nfo.contains("  <title>Episode 3</title>\n"));
+        assert!(nfo.contains("  <episode>3</episode>\n"));
+
+        let nfo = episode_nfo("Show", &EpisodeNumber::new("OVA"));
+        assert!(nfo.contains("  <title>Episode OVA</title>\n"));
+        assert!(!nfo.contains("<episode>"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// `$XDG_CONFIG_HOME/anirust`, or `~/.config/anirust` on every platform
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME")
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("anirust"))
+}
+
+/// `$XDG_DATA_HOME/anirust`, or the platform's data directory
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME")
+        .or_else(dirs::data_dir)
+        .map(|dir| dir.join("anirust"))
+}
+
+/// `$XDG_CACHE_HOME/anirust`, or the platform's cache directory
+///
+/// Falls back to the temporary directory, files kept here are regenerated when missing.
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME")
+        .or_else(dirs::cache_dir)
+        .unwrap_or_else(env::temp_dir)
+        .join("anirust")
+}
+
+/// The spec says relative values are invalid and must be ignored
+fn xdg_dir(var: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+/// Expand a leading `~` and `$VAR` or `${VAR}` references, relative paths are kept relative
+///
+/// Unset variables expand to nothing, like in a shell.
+pub fn expand(path: &str) -> PathBuf {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match dirs::home_dir() {
+            Some(home) => format!("{}{}", home.display(), rest),
+            None => path.to_owned(),
+        },
+        _ => path.to_owned(),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path.as_str();
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remaining) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            // A lone `$` is kept as it is
+            expanded.push('$');
+        } else {
+            expanded.push_str(&env::var(name).unwrap_or_default());
+        }
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+    PathBuf::from(expanded)
+}
+
+/// `path` expanded, and joined to `base` unless it is absolute
+pub fn resolve(base: &Path, path: &str) -> PathBuf {
+    base.join(expand(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home() -> String {
+        dirs::home_dir()
+            .expect("Home directory")
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn expands_a_leading_tilde() {
+        assert_eq!(expand("~"), PathBuf::from(home()));
+        assert_eq!(
+            expand("~/anime"),
+            PathBuf::from(format!("{}/anime", home()))
+        );
+        // Other users' homes and tildes elsewhere are left alone
+        assert_eq!(expand("~bob/anime"), PathBuf::from("~bob/anime"));
+        assert_eq!(expand("a/~/b"), PathBuf::from("a/~/b"));
+    }
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("ANIRUST_TEST_EXPAND", "media");
+        assert_eq!(
+            expand("/mnt/$ANIRUST_TEST_EXPAND/anime"),
+            PathBuf::from("/mnt/media/anime")
+        );
+        assert_eq!(expand("${ANIRUST_TEST_EXPAND}_2"), PathBuf::from("media_2"));
+    }
+
+    #[test]
+    fn unset_variables_expand_to_nothing() {
+        env::remove_var("ANIRUST_TEST_UNSET");
+        assert_eq!(expand("$ANIRUST_TEST_UNSET/anime"), PathBuf::from("/anime"));
+        assert_eq!(expand("a${ANIRUST_TEST_UNSET}b"), PathBuf::from("ab"));
+    }
+
+    #[test]
+    fn lone_dollars_are_kept() {
+        assert_eq!(expand("cost$"), PathBuf::from("cost$"));
+        assert_eq!(expand("a$/b"), PathBuf::from("a$/b"));
+        assert_eq!(expand("${unclosed"), PathBuf::from("${unclosed"));
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_base() {
+        let base = Path::new("/home/user");
+        assert_eq!(resolve(base, "anime"), PathBuf::from("/home/user/anime"));
+        assert_eq!(resolve(base, "/srv/anime"), PathBuf::from("/srv/anime"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+
+use crate::episode::EpisodeNumber;
+
+/// Fraction of the duration after which an episode counts as watched (skips the credits)
+const FINISHED_RATIO: f64 = 0.9;
+const CONNECT_ATTEMPTS: u32 = 50;
+const CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+static SOCKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How far an episode has been watched
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    pub position: f64,
+    pub duration: Option<f64>,
+    pub eof_reached: bool,
+}
+
+impl Progress {
+    pub fn is_finished(&self) -> bool {
+        self.eof_reached
+            || self.duration.is_some_and(|duration| {
+                duration > 0.0 && self.position / duration >= FINISHED_RATIO
+            })
+    }
+}
+
+/// Playback progress keyed by show id, then episode
+pub type ProgressStore = Arc<Mutex<HashMap<String, HashMap<EpisodeNumber, Progress>>>>;
+
+#[derive(Debug, Deserialize)]
+struct IpcEvent {
+    event: Option<String>,
+    name: Option<String>,
+    data: Option<serde_json::Value>,
+}
+
+/// Unique socket path for one player instance
+pub fn socket_path() -> PathBuf {
+    let dir = dirs::runtime_dir().unwrap_or(std::env::temp_dir());
+    dir.join(format!(
+        "anirust-mpv-{}-{}.sock",
+        std::process::id(),
+        SOCKET_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Follow `time-pos`, `duration` and `eof-reached` over mpv's JSON IPC until the player quits
+/// or reaches the end of the episode
+///
+/// The socket only needs to speak mpv's line based JSON protocol, so any server
+/// sending `property-change` events can stand in for mpv. Progress from an earlier
+/// playback of the episode is discarded. `on_change` sees the progress after every event.
+/// Returns the final progress.
+pub async fn track_progress(
+    socket: PathBuf,
+    show_id: String,
+    episode: EpisodeNumber,
+    store: ProgressStore,
+    mut on_change: impl FnMut(&Progress),
+) -> Option<Progress> {
+    store
+        .lock()
+        .expect("Progress store poisoned")
+        .entry(show_id.clone())
+        .or_default()
+        .insert(episode.clone(), Progress::default());
+    let stream = connect(&socket).await?;
+    let (reader, mut writer) = stream.into_split();
+
+    for (id, property) in ["time-pos", "duration", "eof-reached"].iter().enumerate() {
+        let command = format!(
+            "{{\"command\":[\"observe_property\",{},\"{}\"]}}\n",
+            id + 1,
+            property
+        );
+        if writer.write_all(command.as_bytes()).await.is_err() {
+            return None;
+        }
+    }
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(event) = serde_json::from_str::<IpcEvent>(&line) else {
+            continue;
+        };
+        if event.event.as_deref() != Some("property-change") {
+            continue;
+        }
+        let mut episodes = store.lock().expect("Progress store poisoned");
+        let progress = episodes
+            .entry(show_id.clone())
+            .or_default()
+            .entry(episode.clone())
+            .or_default();
+        match (event.name.as_deref(), event.data) {
+            (Some("time-pos"), Some(data)) => {
+                progress.position = data.as_f64().unwrap_or(progress.position)
+            }
+            (Some("duration"), Some(data)) => progress.duration = data.as_f64(),
+            (Some("eof-reached"), Some(data)) => {
+                progress.eof_reached |= data.as_bool().unwrap_or(false)
+            }
+            _ => (),
+        }
+        let progress = *progress;
+        drop(episodes);
+        on_change(&progress);
+        // Players that stay open at the end, like iina, are done with the episode here
+        if progress.eof_reached {
+            break;
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    store
+        .lock()
+        .expect("Progress store poisoned")
+        .get(&show_id)
+        .and_then(|episodes| episodes.get(&episode))
+        .copied()
+}
+
+/// mpv creates the socket shortly after starting, so retry for a while
+async fn connect(socket: &Path) -> Option<UnixStream> {
+    for _ in 0..CONNECT_ATTEMPTS {
+        if let Ok(stream) = UnixStream::connect(socket).await {
+            return Some(stream);
+        }
+        tokio::time::sleep(CONNECT_INTERVAL).await;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::UnixListener};
+
+    use super::*;
+
+    #[test]
+    fn episodes_finish_near_the_end_or_at_eof() {
+        let progress = |position, duration, eof_reached| Progress {
+            position,
+            duration,
+            eof_reached,
+        };
+        assert!(!progress(0.0, None, false).is_finished());
+        assert!(!progress(1200.0, None, false).is_finished());
+        assert!(!progress(1000.0, Some(1440.0), false).is_finished());
+        assert!(progress(1300.0, Some(1440.0), false).is_finished());
+        assert!(progress(10.0, Some(1440.0), true).is_finished());
+        assert!(!progress(10.0, Some(0.0), false).is_finished());
+    }
+
+    #[tokio::test]
+    async fn tracking_stops_at_the_end_of_the_episode() {
+        let socket = socket_path();
+        let listener = UnixListener::bind(&socket).expect("Socket bound");
+        // The player stays open after sending eof-reached
+        let player = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("Connection");
+            stream
+                .write_all(b"{\"event\":\"property-change\",\"id\":3,\"name\":\"eof-reached\",\"data\":true}\n")
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+
+        let progress = tokio::time::timeout(
+            Duration::from_secs(5),
+            track_progress(
+                socket,
+                "show".to_owned(),
+                EpisodeNumber::new("3"),
+                ProgressStore::default(),
+                |_| (),
+            ),
+        )
+        .await
+        .expect("Tracking stopped at the end")
+        .expect("Progress tracked");
+        assert!(progress.is_finished());
+        player.abort();
+    }
+
+    /// Play `events` to a tracker for episode 3, then hang up
+    async fn playback(store: &ProgressStore, events: &'static [&'static str]) -> Progress {
+        let socket = socket_path();
+        let listener = UnixListener::bind(&socket).expect("Socket bound");
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("Connection");
+            for event in events {
+                stream
+                    .write_all(format!("{}\n", event).as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+        track_progress(
+            socket,
+            "show".to_owned(),
+            EpisodeNumber::new("3"),
+            store.clone(),
+            |_| (),
+        )
+        .await
+        .expect("Progress tracked")
+    }
+
+    #[tokio::test]
+    async fn replaying_an_episode_starts_from_scratch() {
+        let store = ProgressStore::default();
+        let watched = playback(
+            &store,
+            &[
+                r#"{"event":"property-change","id":2,"name":"duration","data":1440.0}"#,
+                r#"{"event":"property-change","id":3,"name":"eof-reached","data":true}"#,
+            ],
+        )
+        .await;
+        assert!(watched.is_finished());
+
+        let replayed = playback(
+            &store,
+            &[r#"{"event":"property-change","id":1,"name":"time-pos","data":100.0}"#],
+        )
+        .await;
+        assert_eq!(
+            replayed,
+            Progress {
+                position: 100.0,
+                duration: None,
+                eof_reached: false,
+            }
+        );
+        assert!(!store.lock().unwrap()["show"][&EpisodeNumber::new("3")].is_finished());
+    }
+
+    #[tokio::test]
+    async fn progress_follows_property_changes_until_the_player_quits() {
+        let socket = socket_path();
+        let listener = UnixListener::bind(&socket).expect("Socket bound");
+        let player = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("Connection");
+            let mut commands = vec![0; 256];
+            let read = stream.read(&mut commands).await.unwrap();
+            let events = [
+                r#"{"data":null,"request_id":0,"error":"success"}"#,
+                r#"{"event":"property-change","id":2,"name":"duration","data":1440.0}"#,
+                r#"{"event":"property-change","id":1,"name":"time-pos","data":600.5}"#,
+                "not json",
+                r#"{"event":"seek"}"#,
+                r#"{"event":"property-change","id":1,"name":"time-pos","data":null}"#,
+                r#"{"event":"property-change","id":3,"name":"eof-reached","data":false}"#,
+            ];
+            for event in events {
+                stream
+                    .write_all(format!("{}\n", event).as_bytes())
+                    .await
+                    .unwrap();
+            }
+            String::from_utf8_lossy(&commands[..read]).into_owned()
+        });
+
+        let store = ProgressStore::default();
+        let episode = EpisodeNumber::new("3");
+        let mut positions = Vec::new();
+        let progress = track_progress(
+            socket.clone(),
+            "show".to_owned(),
+            episode.clone(),
+            store.clone(),
+            |progress| positions.push(progress.position),
+        )
+        .await
+        .expect("Progress tracked");
+        assert_eq!(positions, [0.0, 600.5, 600.5, 600.5]);
+        assert_eq!(
+            progress,
+            Progress {
+                position: 600.5,
+                duration: Some(1440.0),
+                eof_reached: false,
+            }
+        );
+        assert_eq!(store.lock().unwrap()["show"][&episode], progress);
+        assert!(!socket.exists());
+
+        let commands = player.await.unwrap();
+        assert!(commands.contains(r#"{"command":["observe_property",1,"time-pos"]}"#));
+    }
+}
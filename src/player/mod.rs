@@ -0,0 +1,498 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    process::{Child, Command},
+};
+
+use crate::{
+    api::url_processor::StreamHeaders,
+    app_state::AppState,
+    config::{Config, MediaPlayer},
+    episode::EpisodeNumber,
+    history::History,
+    paths,
+    utils::is_command_available,
+};
+
+use self::{
+    ipc::{socket_path, track_progress, Progress, ProgressStore},
+    players::{player_for, Media, Player},
+    proxy::StreamProxy,
+    skip::{script_path, SkipTimes},
+};
+
+pub mod ipc;
+pub mod players;
+pub mod proxy;
+pub mod skip;
+
+/// How often the position is written to the watch history during playback
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Playlists and skip scripts are read when the player starts, so ones left behind this long
+/// are no longer needed
+const STALE_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+pub struct AppPlayer {
+    media_player: MediaPlayer,
+    custom_command: Option<Vec<String>>,
+    extra_args: HashMap<String, Vec<String>>,
+    wait: bool,
+    proxy: bool,
+    binge: bool,
+    last_exit: Option<ExitStatus>,
+    progress: ProgressStore,
+    skip: SkipTimes,
+}
+
+impl AppPlayer {
+    pub fn media_player(&self) -> &MediaPlayer {
+        &self.media_player
+    }
+
+    pub fn set_media_player(&mut self, media_player: MediaPlayer) {
+        self.media_player = media_player;
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let player = config.player().cloned().unwrap_or_default();
+        AppPlayer {
+            media_player: player.media_player.unwrap_or_else(MediaPlayer::detect),
+            custom_command: player.custom_command,
+            extra_args: player.extra_args.unwrap_or_default(),
+            wait: player.wait.unwrap_or(false),
+            proxy: player.proxy.unwrap_or(false),
+            binge: false,
+            last_exit: None,
+            progress: ProgressStore::default(),
+            skip: SkipTimes::from_config(config),
+        }
+    }
+
+    pub fn wait(&self) -> bool {
+        self.wait
+    }
+
+    pub fn set_wait(&mut self, wait: bool) {
+        self.wait = wait;
+    }
+
+    /// Whether streams are handed to the player through [`StreamProxy`]
+    pub fn proxy(&self) -> bool {
+        self.proxy
+    }
+
+    /// Automatically play the next episode once the current one is finished
+    pub fn binge(&self) -> bool {
+        self.binge
+    }
+
+    pub fn set_binge(&mut self, binge: bool) {
+        self.binge = binge;
+    }
+
+    /// Exit status of the last player that was waited on
+    pub fn last_exit(&self) -> Option<&ExitStatus> {
+        self.last_exit.as_ref()
+    }
+
+    pub fn set_last_exit(&mut self, last_exit: Option<ExitStatus>) {
+        self.last_exit = last_exit;
+    }
+
+    /// Last known playback position of an episode, when the player reported one
+    pub fn progress(&self, show_id: &str, episode: &EpisodeNumber) -> Option<Progress> {
+        self.progress
+            .lock()
+            .expect("Progress store poisoned")
+            .get(show_id)
+            .and_then(|episodes| episodes.get(episode))
+            .copied()
+    }
+
+    fn player(&self) -> Box<dyn Player> {
+        player_for(self.media_player(), self.custom_command.as_deref())
+    }
+
+    fn extra_args(&self) -> &[String] {
+        self.extra_args
+            .get(self.media_player().as_str())
+            .map(|args| args.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+impl AppPlayer {
+    /// Launch the player for the current episode
+    ///
+    /// The player runs in its own session so it outlives the menu. In `wait` or binge mode
+    /// the exit status is returned once it closes, otherwise the child is reaped in the background.
+    /// A stream proxy, when enabled, lives exactly as long as the player.
+    pub async fn play(&self, state: &AppState) -> io::Result<Option<ExitStatus>> {
+        let mut media = Self::media(state);
+        media.exit_at_end = self.binge();
+        let current_episode = state.current_episode().expect("Episode selected");
+        let proxy = if self.proxy() && !current_episode.is_local() {
+            Some(StreamProxy::start(current_episode.headers().clone()).await?)
+        } else {
+            None
+        };
+        let proxied_url = proxy.as_ref().map(|proxy| proxy.url_for(media.url));
+        let no_headers = StreamHeaders::default();
+        if let Some(url) = &proxied_url {
+            // The proxy sends the headers, the player only talks to localhost
+            media.url = url;
+            media.referer = None;
+            media.headers = &no_headers;
+        }
+        let player = self.player();
+        let socket = socket_path();
+        let mut extra = player.ipc_args(&socket).unwrap_or_default();
+        let tracks_progress = !extra.is_empty();
+        let skip_script = self.skip_script(state, player.as_ref()).await;
+        if let Some(script) = &skip_script {
+            extra.extend(player.script_args(script).unwrap_or_default());
+        }
+        extra.extend_from_slice(self.extra_args());
+
+        let argv = player.command(&media, &extra);
+        let mut child = spawn_detached(&argv, false)?;
+
+        let tracker = tracks_progress.then(|| {
+            let show_id = state.current_show().expect("Show selected").id().to_owned();
+            let episode = state
+                .current_episode()
+                .expect("Episode selected")
+                .ep_number()
+                .clone();
+            let audio_mode = *state.audio_mode();
+            let store = self.progress.clone();
+            tokio::spawn(async move {
+                let save = |progress: &Progress| {
+                    // Runs behind the menu, so there is nowhere to report a failure
+                    let _ = History::update(|history| {
+                        history.record_progress(&show_id, audio_mode, &episode, progress)
+                    });
+                };
+                // anirust may quit before the player does, so the position is kept while watching
+                let mut last_saved = Instant::now();
+                let progress = track_progress(
+                    socket,
+                    show_id.clone(),
+                    episode.clone(),
+                    store,
+                    |progress| {
+                        if last_saved.elapsed() >= PROGRESS_SAVE_INTERVAL {
+                            last_saved = Instant::now();
+                            save(progress);
+                        }
+                    },
+                )
+                .await;
+                if let Some(progress) = progress {
+                    save(&progress);
+                }
+            })
+        });
+
+        if self.wait() || self.binge() {
+            let status = child.wait().await?;
+            // Let the tracker record the final events before reporting back
+            if let Some(tracker) = tracker {
+                let _ = tracker.await;
+            }
+            drop(proxy);
+            remove_temporary(skip_script);
+            Ok(Some(status))
+        } else {
+            tokio::spawn(async move {
+                let status = child.wait().await;
+                drop(proxy);
+                remove_temporary(skip_script);
+                status
+            });
+            Ok(None)
+        }
+    }
+
+    /// Write the opening and ending skip script for the current episode
+    ///
+    /// Needs an mpv based player and a show with a MAL id. Nothing is written when no skip
+    /// times are known, so playback never depends on the lookup.
+    async fn skip_script(&self, state: &AppState, player: &dyn Player) -> Option<PathBuf> {
+        if !self.skip.enabled() {
+            return None;
+        }
+        let path = script_path();
+        player.script_args(&path)?;
+        let mal_id = state.current_show()?.mal_id()?;
+        let episode = state.current_episode()?.ep_number();
+        let ranges = self.skip.fetch(mal_id, episode).await;
+        if ranges.is_empty() {
+            return None;
+        }
+        self.skip.write_script(&path, &ranges).ok()?;
+        remove_stale_files(&paths::cache_dir(), STALE_FILE_AGE);
+        Some(path)
+    }
+
+    /// Launch the player on an M3U `playlist`
+    ///
+    /// Players that can read the playlist are piped it on stdin, the others are handed a file
+    /// in the cache directory, removed once the player exits.
+    /// Entries can't be told apart by the player, so no progress is tracked. `headers` are
+    /// sent with every entry by players that take them on the command line, unless the
+    /// entries go through `proxy`. The proxy only serves the player while anirust runs, so
+    /// the player is then waited on whatever `wait` says.
+    pub async fn play_playlist(
+        &self,
+        playlist: &str,
+        title: &str,
+        headers: &StreamHeaders,
+        proxy: Option<StreamProxy>,
+    ) -> io::Result<Option<ExitStatus>> {
+        let player = self.player();
+        let (url, file) = match player.stdin_playlist() {
+            Some(stdin) => (stdin.to_owned(), None),
+            None => {
+                let path = write_playlist(playlist)?;
+                (path.to_string_lossy().into_owned(), Some(path))
+            }
+        };
+        let no_headers = StreamHeaders::default();
+        let headers = if proxy.is_some() {
+            &no_headers
+        } else {
+            headers
+        };
+        let media = Media {
+            url: &url,
+            title: title.to_owned(),
+            // A playlist has no single subtitle track to hand over
+            sub: None,
+            referer: headers.referer(),
+            headers,
+            start: None,
+            exit_at_end: false,
+        };
+        let argv = player.command(&media, self.extra_args());
+        let mut child = spawn_detached(&argv, file.is_none())?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(playlist.as_bytes()).await?;
+            // Closing stdin marks the end of the playlist
+            drop(stdin);
+        }
+        if self.wait() || proxy.is_some() {
+            let status = child.wait().await?;
+            drop(proxy);
+            remove_temporary(file);
+            Ok(Some(status))
+        } else {
+            tokio::spawn(async move {
+                let status = child.wait().await;
+                remove_temporary(file);
+                status
+            });
+            Ok(None)
+        }
+    }
+
+    fn media(state: &AppState) -> Media<'_> {
+        let current_episode = state.current_episode().expect("Episode selected");
+        Media {
+            url: current_episode.url(),
+            title: format!(
+                "{} Episode {}",
+                state
+                    .current_show()
+                    .expect("Show selected")
+                    .name()
+                    .expect("Show has name"),
+                current_episode.ep_number()
+            ),
+            sub: current_episode.default_subtitle(),
+            referer: current_episode.headers().referer(),
+            headers: current_episode.headers(),
+            start: current_episode.resume_at(),
+            exit_at_end: false,
+        }
+    }
+
+    pub async fn is_available(&self) -> Result<(), Box<io::Error>> {
+        let player = self.player();
+        let error_string = if player.executable().is_empty() {
+            "No custom_command configured for the custom player".to_owned()
+        } else if !is_command_available(player.executable()) {
+            format!("Could not find media player: {}", player.executable())
+        } else {
+            return Ok(());
+        };
+        Err(Box::new(io::Error::other(error_string)))
+    }
+}
+
+fn remove_temporary(file: Option<PathBuf>) {
+    if let Some(file) = file {
+        let _ = fs::remove_file(file);
+    }
+}
+
+/// Write `playlist` to the cache directory, removing files left behind by earlier runs
+fn write_playlist(playlist: &str) -> io::Result<PathBuf> {
+    let dir = paths::cache_dir();
+    fs::create_dir_all(&dir)?;
+    remove_stale_files(&dir, STALE_FILE_AGE);
+    let path = dir.join(format!("anirust-playlist-{}.m3u", std::process::id()));
+    fs::write(&path, playlist)?;
+    Ok(path)
+}
+
+/// Remove playlists and skip scripts older than `max_age` from `dir`
+///
+/// Without `wait`, anirust usually exits before the player and can't remove them itself.
+fn remove_stale_files(dir: &Path, max_age: Duration) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let temporary = (name.starts_with("anirust-playlist-") && name.ends_with(".m3u"))
+            || (name.starts_with("anirust-skip-") && name.ends_with(".lua"));
+        if !temporary {
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > max_age);
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// `piped_stdin` leaves stdin open for the caller to write to, instead of closing it
+fn spawn_detached(argv: &[String], piped_stdin: bool) -> io::Result<Child> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or(io::Error::other("Empty player command"))?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(if piped_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    // SAFETY: setsid is async-signal-safe and touches no state shared with the parent
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::SystemTime};
+
+    use crate::app_state::{CurrentEpisode, CurrentShow};
+
+    use super::*;
+
+    #[test]
+    fn custom_players_get_the_default_subtitle() {
+        let mut state = AppState::from_config(&Config::default()).expect("State from defaults");
+        state.set_show(CurrentShow::new(
+            "id".to_owned(),
+            Some("Show".to_owned()),
+            None,
+            vec![EpisodeNumber::new("5")],
+        ));
+        let mut episode = CurrentEpisode::new(
+            EpisodeNumber::new("5"),
+            "https://cdn.example.com/5.mp4".to_owned(),
+            StreamHeaders::default(),
+        );
+        let subtitle = |lang: &str, default: bool| {
+            serde_json::from_value(serde_json::json!({
+                "lang": lang,
+                "label": lang,
+                "default": default,
+                "paring": "",
+                "src": format!("https://cdn.example.com/5.{}.vtt", lang),
+            }))
+            .expect("API subtitle")
+        };
+        episode.set_subtitles(vec![subtitle("es", false), subtitle("en", true)]);
+        state.set_episode(episode);
+
+        let template = vec![
+            "celluloid".to_owned(),
+            "--sub-file={sub}".to_owned(),
+            "{url}".to_owned(),
+        ];
+        let argv = player_for(&MediaPlayer::Custom, Some(&template))
+            .command(&AppPlayer::media(&state), &[]);
+        assert_eq!(
+            argv,
+            [
+                "celluloid",
+                "--sub-file=https://cdn.example.com/5.en.vtt",
+                "https://cdn.example.com/5.mp4"
+            ]
+        );
+    }
+
+    #[test]
+    fn only_old_playlists_and_skip_scripts_are_removed() {
+        let dir = std::env::temp_dir().join(format!("anirust-test-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        for (name, modified) in [
+            ("anirust-playlist-1.m3u", Some(old)),
+            ("anirust-skip-1-0.lua", Some(old)),
+            ("notes.txt", Some(old)),
+            ("anirust-playlist-2.m3u", None),
+            ("anirust-skip-2-0.lua", None),
+        ] {
+            let file = File::create(dir.join(name)).unwrap();
+            if let Some(modified) = modified {
+                file.set_modified(modified).unwrap();
+            }
+        }
+
+        remove_stale_files(&dir, Duration::from_secs(60 * 60));
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "anirust-playlist-2.m3u",
+                "anirust-skip-2-0.lua",
+                "notes.txt"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
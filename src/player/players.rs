@@ -0,0 +1,303 @@
+use std::path::Path;
+
+use crate::{api::url_processor::StreamHeaders, config::MediaPlayer};
+
+/// What a player is asked to open
+#[derive(Debug)]
+pub struct Media<'a> {
+    pub url: &'a str,
+    pub title: String,
+    pub sub: Option<&'a str>,
+    pub referer: Option<&'a str>,
+    /// Headers the stream has to be requested with
+    pub headers: &'a StreamHeaders,
+    /// Position in seconds to resume playback from
+    pub start: Option<f64>,
+    /// The player has to exit when playback ends, so binge mode can move on
+    pub exit_at_end: bool,
+}
+
+/// A program able to play a stream URL
+pub trait Player: Send + Sync {
+    /// Executable looked up on `PATH` before launching
+    fn executable(&self) -> &str;
+    /// Full argv, program first, with the player's `extra` arguments in place
+    fn command(&self, media: &Media, extra: &[String]) -> Vec<String>;
+    /// Arguments exposing mpv's JSON IPC on `socket`, for players built on mpv
+    fn ipc_args(&self, _socket: &Path) -> Option<Vec<String>> {
+        None
+    }
+    /// Arguments loading the lua `script`, for players built on mpv
+    fn script_args(&self, _script: &Path) -> Option<Vec<String>> {
+        None
+    }
+    /// What to open instead of a URL to read a playlist piped to stdin, for players that can
+    fn stdin_playlist(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Build the player selected in the configuration
+pub fn player_for(
+    media_player: &MediaPlayer,
+    custom_command: Option<&[String]>,
+) -> Box<dyn Player> {
+    match media_player {
+        MediaPlayer::IINA => Box::new(Iina),
+        MediaPlayer::MPV => Box::new(Mpv),
+        MediaPlayer::VLC => Box::new(Vlc),
+        MediaPlayer::Custom => Box::new(Custom {
+            template: custom_command.map(|c| c.to_vec()).unwrap_or_default(),
+        }),
+    }
+}
+
+pub struct Iina;
+
+impl Player for Iina {
+    fn executable(&self) -> &str {
+        "iina"
+    }
+
+    fn command(&self, media: &Media, extra: &[String]) -> Vec<String> {
+        let mut args = vec![self.executable().to_string(), "--no-stdin".to_string()];
+        // Ties iina-cli to the app, which stays open after the episode ends. Without it the
+        // wait for the player ends with its IPC connection instead.
+        if !media.exit_at_end {
+            args.push("--keep-running".to_string());
+        }
+        args.push(format!("--mpv-force-media-title={}", media.title));
+        if let Some(start) = media.start {
+            args.push(format!("--mpv-start={}", start));
+        }
+        args.extend(
+            mpv_header_args(media.headers)
+                .into_iter()
+                .map(|arg| arg.replacen("--", "--mpv-", 1)),
+        );
+        args.extend_from_slice(extra);
+        args.push(media.url.to_string());
+        args
+    }
+
+    fn ipc_args(&self, socket: &Path) -> Option<Vec<String>> {
+        Some(vec![format!("--mpv-input-ipc-server={}", socket.display())])
+    }
+
+    fn script_args(&self, script: &Path) -> Option<Vec<String>> {
+        Some(vec![format!("--mpv-script={}", script.display())])
+    }
+}
+
+pub struct Mpv;
+
+impl Player for Mpv {
+    fn executable(&self) -> &str {
+        "mpv"
+    }
+
+    fn command(&self, media: &Media, extra: &[String]) -> Vec<String> {
+        let mut args = vec![
+            self.executable().to_string(),
+            format!("--force-media-title={}", media.title),
+        ];
+        if let Some(start) = media.start {
+            args.push(format!("--start={}", start));
+        }
+        args.extend(mpv_header_args(media.headers));
+        args.extend_from_slice(extra);
+        args.push(media.url.to_string());
+        args
+    }
+
+    fn ipc_args(&self, socket: &Path) -> Option<Vec<String>> {
+        Some(vec![format!("--input-ipc-server={}", socket.display())])
+    }
+
+    fn script_args(&self, script: &Path) -> Option<Vec<String>> {
+        Some(vec![format!("--script={}", script.display())])
+    }
+
+    fn stdin_playlist(&self) -> Option<&str> {
+        Some("--playlist=-")
+    }
+}
+
+pub struct Vlc;
+
+impl Player for Vlc {
+    fn executable(&self) -> &str {
+        "vlc"
+    }
+
+    fn command(&self, media: &Media, extra: &[String]) -> Vec<String> {
+        let mut args = vec![
+            self.executable().to_string(),
+            "--play-and-exit".to_string(),
+            format!("--meta-title={}", media.title),
+        ];
+        if let Some(start) = media.start {
+            args.push(format!("--start-time={}", start));
+        }
+        // vlc can only set these two headers
+        if let Some(referer) = media.headers.referer() {
+            args.push(format!("--http-referrer={}", referer));
+        }
+        if let Some(user_agent) = media.headers.user_agent() {
+            args.push(format!("--http-user-agent={}", user_agent));
+        }
+        args.extend_from_slice(extra);
+        args.push(media.url.to_string());
+        args
+    }
+}
+
+/// `--referrer` for the Referer, `--http-header-fields-append` for every other header
+fn mpv_header_args(headers: &StreamHeaders) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if name.eq_ignore_ascii_case("Referer") {
+                format!("--referrer={}", value)
+            } else {
+                format!("--http-header-fields-append={}: {}", name, value)
+            }
+        })
+        .collect()
+}
+
+/// Player launched from a user supplied argv template
+///
+/// `{url}`, `{title}`, `{sub}`, `{referer}` and `{start}` are substituted in every element.
+/// Elements referring to a value that is not available are left out.
+pub struct Custom {
+    template: Vec<String>,
+}
+
+impl Player for Custom {
+    fn executable(&self) -> &str {
+        self.template.first().map(|s| s.as_str()).unwrap_or("")
+    }
+
+    fn command(&self, media: &Media, extra: &[String]) -> Vec<String> {
+        let mut args: Vec<String> = self
+            .template
+            .iter()
+            .filter_map(|arg| substitute(arg, media))
+            .collect();
+        args.extend_from_slice(extra);
+        args
+    }
+}
+
+fn substitute(arg: &str, media: &Media) -> Option<String> {
+    let mut arg = arg
+        .replace("{url}", media.url)
+        .replace("{title}", &media.title);
+    let start = media.start.map(|start| start.to_string());
+    for (placeholder, value) in [
+        ("{sub}", media.sub),
+        ("{referer}", media.referer),
+        ("{start}", start.as_deref()),
+    ] {
+        if arg.contains(placeholder) {
+            arg = arg.replace(placeholder, value?);
+        }
+    }
+    Some(arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(args: &[&str]) -> Box<dyn Player> {
+        let template: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        player_for(&MediaPlayer::Custom, Some(&template))
+    }
+
+    #[test]
+    fn placeholders_are_substituted_in_every_element() {
+        let headers = StreamHeaders::default();
+        let media = Media {
+            url: "https://example.com/ep5.mp4",
+            title: "Show Episode 5".to_owned(),
+            sub: Some("/tmp/ep5.ass"),
+            referer: Some("https://example.com/"),
+            headers: &headers,
+            start: Some(90.5),
+            exit_at_end: false,
+        };
+        let player = template(&[
+            "celluloid",
+            "--title={title}",
+            "--sub-file={sub}",
+            "--referrer={referer}",
+            "--start={start}",
+            "{url}",
+        ]);
+        assert_eq!(player.executable(), "celluloid");
+        assert_eq!(
+            player.command(&media, &["--fs".to_owned()]),
+            [
+                "celluloid",
+                "--title=Show Episode 5",
+                "--sub-file=/tmp/ep5.ass",
+                "--referrer=https://example.com/",
+                "--start=90.5",
+                "https://example.com/ep5.mp4",
+                "--fs",
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_with_missing_values_are_left_out() {
+        let headers = StreamHeaders::default();
+        let media = Media {
+            url: "https://example.com/ep5.mp4",
+            title: "Show Episode 5".to_owned(),
+            sub: None,
+            referer: None,
+            headers: &headers,
+            start: None,
+            exit_at_end: false,
+        };
+        let player = template(&[
+            "celluloid",
+            "--sub-file={sub}",
+            "--mpv-options=referrer={referer},start={start}",
+            "{url}",
+        ]);
+        assert_eq!(
+            player.command(&media, &[]),
+            ["celluloid", "https://example.com/ep5.mp4"]
+        );
+    }
+
+    #[test]
+    fn iina_only_keeps_running_outside_binge_mode() {
+        let headers = StreamHeaders::default();
+        let mut media = Media {
+            url: "https://example.com/ep5.mp4",
+            title: "Show Episode 5".to_owned(),
+            sub: None,
+            referer: None,
+            headers: &headers,
+            start: None,
+            exit_at_end: false,
+        };
+        let keeps_running = |media: &Media| {
+            Iina.command(media, &[])
+                .contains(&"--keep-running".to_owned())
+        };
+        assert!(keeps_running(&media));
+        media.exit_at_end = true;
+        assert!(!keeps_running(&media));
+    }
+
+    #[test]
+    fn an_empty_template_has_no_executable() {
+        assert_eq!(template(&[]).executable(), "");
+    }
+}
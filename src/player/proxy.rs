@@ -0,0 +1,378 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use reqwest::{header, Method, Url};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::api::url_processor::StreamHeaders;
+
+/// Local HTTP proxy that fetches a stream with its provider's headers
+///
+/// For players that cannot send custom headers. Upstream URLs are hex encoded into the
+/// request path, and HLS playlists are rewritten so their segments go through the proxy too.
+/// The proxy stops when dropped.
+pub struct StreamProxy {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+struct Upstream {
+    client: reqwest::Client,
+    headers: StreamHeaders,
+    addr: SocketAddr,
+}
+
+impl StreamProxy {
+    pub async fn start(headers: StreamHeaders) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let upstream = Arc::new(Upstream {
+            client: reqwest::Client::new(),
+            headers,
+            addr,
+        });
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let upstream = upstream.clone();
+                tokio::spawn(async move {
+                    // The player retries or gives up on its own, nothing to report here
+                    let _ = upstream.serve(stream).await;
+                });
+            }
+        });
+        Ok(StreamProxy { addr, task })
+    }
+
+    /// Address the player should open instead of `url`
+    pub fn url_for(&self, url: &str) -> String {
+        proxy_url(self.addr, url)
+    }
+}
+
+impl Drop for StreamProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Upstream {
+    async fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await?;
+        let mut range = None;
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("range") {
+                    range = Some(value.trim().to_owned());
+                }
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().and_then(upstream_url);
+        let stream = stream.get_mut();
+        let url = match (method, target) {
+            ("GET" | "HEAD", Some(url)) => url,
+            _ => return respond_status(stream, "400 Bad Request").await,
+        };
+
+        // HEAD is forwarded as is so probing players don't download the whole stream
+        let upstream_method = if method == "HEAD" {
+            Method::HEAD
+        } else {
+            Method::GET
+        };
+        let mut request = self.client.request(upstream_method, url);
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        let mut response = match request.send().await {
+            Ok(response) => response,
+            Err(_) => return respond_status(stream, "502 Bad Gateway").await,
+        };
+
+        // Relative URIs in a playlist resolve against where redirects ended up
+        let url = response.url().clone();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let is_playlist =
+            content_type.to_lowercase().contains("mpegurl") || url.path().ends_with(".m3u8");
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nConnection: close\r\n",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or_default()
+        );
+        if !content_type.is_empty() {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+
+        if is_playlist && status.is_success() && method == "GET" {
+            let playlist = match response.text().await {
+                Ok(playlist) => self.rewrite_playlist(&url, &playlist),
+                Err(_) => return respond_status(stream, "502 Bad Gateway").await,
+            };
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", playlist.len()));
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(playlist.as_bytes()).await?;
+            return stream.flush().await;
+        }
+
+        // A rewritten playlist has a different length than the upstream one
+        let length = (!is_playlist).then_some(header::CONTENT_LENGTH);
+        for name in length
+            .into_iter()
+            .chain([header::CONTENT_RANGE, header::ACCEPT_RANGES])
+        {
+            if let Some(value) = response
+                .headers()
+                .get(&name)
+                .and_then(|value| value.to_str().ok())
+            {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).await?;
+        if method == "GET" {
+            while let Ok(Some(chunk)) = response.chunk().await {
+                stream.write_all(&chunk).await?;
+            }
+        }
+        stream.flush().await
+    }
+
+    /// Point every URI in an HLS playlist back at the proxy
+    fn rewrite_playlist(&self, base: &Url, playlist: &str) -> String {
+        playlist
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim();
+                if trimmed.is_empty() {
+                    line.to_owned()
+                } else if trimmed.starts_with('#') {
+                    self.rewrite_uri_attribute(base, line)
+                } else {
+                    match base.join(trimmed) {
+                        Ok(url) => proxy_url(self.addr, url.as_str()),
+                        Err(_) => line.to_owned(),
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Tags like `#EXT-X-KEY` and `#EXT-X-MAP` carry their own `URI="..."`
+    fn rewrite_uri_attribute(&self, base: &Url, line: &str) -> String {
+        let Some(start) = line.find("URI=\"").map(|i| i + "URI=\"".len()) else {
+            return line.to_owned();
+        };
+        let Some(len) = line[start..].find('"') else {
+            return line.to_owned();
+        };
+        match base.join(&line[start..start + len]) {
+            Ok(url) => format!(
+                "{}{}{}",
+                &line[..start],
+                proxy_url(self.addr, url.as_str()),
+                &line[start + len..]
+            ),
+            Err(_) => line.to_owned(),
+        }
+    }
+}
+
+/// `http://127.0.0.1:PORT/<hex url>/<file name>`
+///
+/// The original file name is kept so players can still tell the stream type from the URL.
+fn proxy_url(addr: SocketAddr, url: &str) -> String {
+    let name = Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back().map(|s| s.to_owned()))
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "stream".to_owned());
+    format!("http://{}/{}/{}", addr, hex::encode(url), name)
+}
+
+fn upstream_url(target: &str) -> Option<Url> {
+    let encoded = target.trim_start_matches('/').split('/').next()?;
+    let url = String::from_utf8(hex::decode(encoded).ok()?).ok()?;
+    Url::parse(&url).ok()
+}
+
+async fn respond_status(stream: &mut TcpStream, status: &str) -> io::Result<()> {
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                status
+            )
+            .as_bytes(),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn upstream() -> Upstream {
+        Upstream {
+            client: reqwest::Client::new(),
+            headers: StreamHeaders::default(),
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+        }
+    }
+
+    #[test]
+    fn proxy_urls_round_trip_to_the_upstream_url() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+        let url = "https://cdn.example.com/hls/720/index.m3u8?token=a%2Fb&expires=1";
+        let proxied = proxy_url(addr, url);
+        assert!(proxied.starts_with("http://127.0.0.1:8080/"));
+        assert!(proxied.ends_with("/index.m3u8"));
+
+        let target = Url::parse(&proxied).unwrap();
+        assert_eq!(upstream_url(target.path()).unwrap().as_str(), url);
+
+        assert!(proxy_url(addr, "https://cdn.example.com/").ends_with("/stream"));
+        assert!(upstream_url("/not-hex/index.m3u8").is_none());
+    }
+
+    #[test]
+    fn playlist_uris_point_back_at_the_proxy() {
+        let upstream = upstream();
+        let proxied = |url| proxy_url(upstream.addr, url);
+        let base = Url::parse("https://cdn.example.com/hls/720/index.m3u8").unwrap();
+        let playlist = [
+            "#EXTM3U",
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x1",
+            "#EXT-X-MAP:URI=\"/init.mp4\"",
+            "#EXTINF:4.0,",
+            "seg-1.ts",
+            "",
+            "#EXTINF:4.0,",
+            "https://other.example.com/seg-2.ts",
+        ]
+        .join("\n");
+
+        assert_eq!(
+            upstream.rewrite_playlist(&base, &playlist),
+            [
+                "#EXTM3U".to_owned(),
+                format!(
+                    "#EXT-X-KEY:METHOD=AES-128,URI=\"{}\",IV=0x1",
+                    proxied("https://cdn.example.com/hls/720/key.bin")
+                ),
+                format!(
+                    "#EXT-X-MAP:URI=\"{}\"",
+                    proxied("https://cdn.example.com/init.mp4")
+                ),
+                "#EXTINF:4.0,".to_owned(),
+                proxied("https://cdn.example.com/hls/720/seg-1.ts"),
+                String::new(),
+                "#EXTINF:4.0,".to_owned(),
+                proxied("https://other.example.com/seg-2.ts"),
+            ]
+            .join("\n")
+        );
+    }
+
+    /// Serve a redirecting playlist and a video, recording each request line
+    async fn cdn() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![0; 4096];
+                let read = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let request_line = request.lines().next().unwrap_or_default().to_owned();
+                let response = match request_line.split_whitespace().nth(1) {
+                    Some("/old/index.m3u8") => {
+                        "HTTP/1.1 302 Found\r\nLocation: /new/index.m3u8\r\nContent-Length: 0\r\n\r\n"
+                            .to_owned()
+                    }
+                    Some("/new/index.m3u8") => "HTTP/1.1 200 OK\r\nContent-Type: application/vnd.apple.mpegurl\r\nContent-Length: 7\r\n\r\nseg.ts\n".to_owned(),
+                    Some("/video.mp4") if request_line.starts_with("HEAD") => {
+                        "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 4\r\n\r\n"
+                            .to_owned()
+                    }
+                    _ => "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 4\r\n\r\ndata"
+                        .to_owned(),
+                };
+                log.lock().unwrap().push(request_line);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn redirected_playlists_resolve_against_the_final_url() {
+        let (cdn, _) = cdn().await;
+        let proxy = StreamProxy::start(StreamHeaders::default())
+            .await
+            .expect("Proxy started");
+        let playlist = reqwest::get(proxy.url_for(&format!("http://{}/old/index.m3u8", cdn)))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(
+            playlist,
+            proxy.url_for(&format!("http://{}/new/seg.ts", cdn))
+        );
+    }
+
+    #[tokio::test]
+    async fn head_requests_are_not_forwarded_as_get() {
+        let (cdn, requests) = cdn().await;
+        let proxy = StreamProxy::start(StreamHeaders::default())
+            .await
+            .expect("Proxy started");
+        let response = reqwest::Client::new()
+            .head(proxy.url_for(&format!("http://{}/video.mp4", cdn)))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            "4",
+            "Length of the upstream file"
+        );
+        assert_eq!(
+            requests.lock().unwrap().as_slice(),
+            ["HEAD /video.mp4 HTTP/1.1"]
+        );
+    }
+}
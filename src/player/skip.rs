@@ -0,0 +1,298 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{config::Config, episode::EpisodeNumber, paths};
+
+pub const DEFAULT_API_URL: &str = "https://api.aniskip.com";
+pub const DEFAULT_KEY: &str = "TAB";
+/// Playback waits on the lookup, so a slow API must not hold it up for long
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+static NEXT_SCRIPT: AtomicUsize = AtomicUsize::new(0);
+
+/// Unique path for a skip script of this process
+pub fn script_path() -> PathBuf {
+    paths::cache_dir().join(format!(
+        "anirust-skip-{}-{}.lua",
+        std::process::id(),
+        NEXT_SCRIPT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Looks up opening and ending times and turns them into an mpv script
+#[derive(Debug)]
+pub struct SkipTimes {
+    client: reqwest::Client,
+    enabled: bool,
+    auto: bool,
+    api_url: String,
+    key: String,
+}
+
+/// A part of the episode that can be skipped, in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkipRange {
+    pub name: &'static str,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Deserialize)]
+struct SkipResponse {
+    found: bool,
+    #[serde(default)]
+    results: Vec<SkipResult>,
+}
+
+#[derive(Deserialize)]
+struct SkipResult {
+    interval: Interval,
+    #[serde(rename = "skipType")]
+    skip_type: String,
+}
+
+#[derive(Deserialize)]
+struct Interval {
+    #[serde(rename = "startTime")]
+    start_time: f64,
+    #[serde(rename = "endTime")]
+    end_time: f64,
+}
+
+impl SkipTimes {
+    pub fn from_config(config: &Config) -> Self {
+        let skip = config.skip().cloned().unwrap_or_default();
+        SkipTimes {
+            client: reqwest::Client::builder()
+                .timeout(LOOKUP_TIMEOUT)
+                .build()
+                .expect("Failed to build client"),
+            enabled: skip.enabled.unwrap_or(true),
+            auto: skip.auto.unwrap_or(false),
+            api_url: skip
+                .api_url
+                .unwrap_or(DEFAULT_API_URL.to_owned())
+                .trim_end_matches('/')
+                .to_owned(),
+            key: skip.key.unwrap_or(DEFAULT_KEY.to_owned()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Opening and ending of an episode, empty when the API doesn't know them
+    pub async fn fetch(&self, mal_id: &str, episode: &EpisodeNumber) -> Vec<SkipRange> {
+        let url = format!(
+            "{}/v2/skip-times/{}/{}?types[]=op&types[]=ed&episodeLength=0",
+            self.api_url, mal_id, episode
+        );
+        let response = match self.client.get(url).send().await {
+            Ok(response) if response.status().is_success() => response,
+            _ => return Vec::new(),
+        };
+        match response.json::<SkipResponse>().await {
+            Ok(skip) if skip.found => skip
+                .results
+                .into_iter()
+                .filter_map(|result| {
+                    let name = match result.skip_type.as_str() {
+                        "op" => "Opening",
+                        "ed" => "Ending",
+                        _ => return None,
+                    };
+                    Some(SkipRange {
+                        name,
+                        start: result.interval.start_time,
+                        end: result.interval.end_time,
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Write an mpv lua script adding chapters and a skip key for `ranges`
+    ///
+    /// The caller removes the script once the player has exited.
+    pub fn write_script(&self, path: &Path, ranges: &[SkipRange]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.script(ranges))
+    }
+
+    fn script(&self, ranges: &[SkipRange]) -> String {
+        let ranges: String = ranges
+            .iter()
+            .map(|range| {
+                format!(
+                    "    {{ name = {:?}, start = {}, stop = {} }},\n",
+                    range.name, range.start, range.end
+                )
+            })
+            .collect();
+        format!(
+            r#"-- Generated by anirust
+local ranges = {{
+{ranges}}}
+local auto_skip = {auto}
+local skipped = {{}}
+
+local function current_range()
+    local pos = mp.get_property_number("time-pos")
+    if not pos then return nil end
+    for i, range in ipairs(ranges) do
+        if pos >= range.start and pos < range.stop then return i, range end
+    end
+end
+
+local function skip()
+    local _, range = current_range()
+    if range then
+        mp.commandv("seek", tostring(range.stop), "absolute")
+        mp.osd_message("Skipped " .. range.name)
+    end
+end
+
+mp.register_event("file-loaded", function()
+    local chapters = mp.get_property_native("chapter-list") or {{}}
+    for _, range in ipairs(ranges) do
+        table.insert(chapters, {{ title = range.name, time = range.start }})
+        table.insert(chapters, {{ title = "Episode", time = range.stop }})
+    end
+    table.sort(chapters, function(a, b) return a.time < b.time end)
+    mp.set_property_native("chapter-list", chapters)
+end)
+
+mp.add_key_binding({key:?}, "anirust-skip", skip)
+
+if auto_skip then
+    mp.observe_property("time-pos", "number", function()
+        local i, range = current_range()
+        if range and not skipped[i] then
+            skipped[i] = true
+            skip()
+        end
+    end)
+end
+"#,
+            ranges = ranges,
+            auto = self.auto,
+            key = self.key,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+
+    fn skip_times(api_url: String, auto: bool) -> SkipTimes {
+        SkipTimes {
+            client: reqwest::Client::new(),
+            enabled: true,
+            auto,
+            api_url,
+            key: DEFAULT_KEY.to_owned(),
+        }
+    }
+
+    /// AniSkip stand-in answering a single lookup, resolves to the request line
+    async fn stub(status: &str, body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Stub bound");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let status = status.to_owned();
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("Connection");
+            let mut request = vec![0; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).into_owned();
+            request.lines().next().unwrap_or_default().to_owned()
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn openings_and_endings_are_looked_up() {
+        let (url, request) = stub(
+            "200 OK",
+            r#"{"found":true,"results":[
+                {"interval":{"startTime":85.5,"endTime":175.5},"skipType":"op","skipId":"a","episodeLength":1420},
+                {"interval":{"startTime":1300,"endTime":1390},"skipType":"ed","skipId":"b","episodeLength":1420},
+                {"interval":{"startTime":0,"endTime":20},"skipType":"recap","skipId":"c","episodeLength":1420}
+            ]}"#,
+        )
+        .await;
+        let ranges = skip_times(url, false)
+            .fetch("21", &EpisodeNumber::new("5"))
+            .await;
+        assert_eq!(
+            ranges,
+            [
+                SkipRange {
+                    name: "Opening",
+                    start: 85.5,
+                    end: 175.5
+                },
+                SkipRange {
+                    name: "Ending",
+                    start: 1300.0,
+                    end: 1390.0
+                },
+            ]
+        );
+        assert!(request
+            .await
+            .unwrap()
+            .starts_with("GET /v2/skip-times/21/5?types[]=op&types[]=ed&episodeLength=0 "));
+    }
+
+    #[tokio::test]
+    async fn unknown_episodes_have_nothing_to_skip() {
+        let (url, _) = stub("200 OK", r#"{"found":false,"results":[]}"#).await;
+        assert!(skip_times(url, false)
+            .fetch("21", &EpisodeNumber::new("5"))
+            .await
+            .is_empty());
+
+        let (url, _) = stub("404 Not Found", r#"{"message":"not found"}"#).await;
+        assert!(skip_times(url, false)
+            .fetch("21", &EpisodeNumber::new("5"))
+            .await
+            .is_empty());
+    }
+
+    #[test]
+    fn script_lists_the_ranges_and_binds_the_key() {
+        let ranges = [SkipRange {
+            name: "Opening",
+            start: 85.5,
+            end: 175.0,
+        }];
+        let script = skip_times(String::new(), true).script(&ranges);
+        assert!(script.contains("    { name = \"Opening\", start = 85.5, stop = 175 },\n"));
+        assert!(script.contains("local auto_skip = true\n"));
+        assert!(script.contains("mp.add_key_binding(\"TAB\", \"anirust-skip\", skip)"));
+    }
+}
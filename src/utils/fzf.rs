@@ -1,8 +1,9 @@
 use skim::prelude::*;
 use std::io::Cursor;
 
+/// `None` when the menu is dismissed with escape
 #[allow(clippy::unwrap_used)]
-pub fn skim_menu(options: &[&str], prompt: Option<&str>) -> String {
+pub fn skim_menu(options: &[&str], prompt: Option<&str>) -> Option<String> {
     let choices: String = options.join("\n");
 
     let options = SkimOptionsBuilder::default()
@@ -16,12 +17,12 @@ pub fn skim_menu(options: &[&str], prompt: Option<&str>) -> String {
         let item_reader = SkimItemReader::default();
         let items = item_reader.of_bufread(Cursor::new(choices.clone().into_bytes()));
 
-        let selected_items = Skim::run_with(&options, Some(items.clone()))
-            .map(|out| out.selected_items)
-            .unwrap_or_default();
-
-        if let Some(selected_item) = selected_items.first() {
-            return selected_item.output().to_string();
+        let output = Skim::run_with(&options, Some(items))?;
+        if output.is_abort {
+            return None;
+        }
+        if let Some(selected_item) = output.selected_items.first() {
+            return Some(selected_item.output().to_string());
         }
     }
 }
@@ -1,6 +1,11 @@
 pub mod fzf;
+pub mod picker;
 
-use std::process::{Command, Stdio};
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
 
 pub fn is_command_available(cmd: &str) -> bool {
     Command::new("which")
@@ -11,3 +16,45 @@ pub fn is_command_available(cmd: &str) -> bool {
         .map(|status| status.success())
         .unwrap_or(false)
 }
+
+/// Playback position as `m:ss`
+pub fn format_position(seconds: f64) -> String {
+    format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60)
+}
+
+/// Put `text` on the system clipboard with wl-copy, xclip or pbcopy, whichever is installed
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut tools: Vec<(&str, &[&str])> = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(("wl-copy", &[]));
+    }
+    tools.push(("xclip", &["-selection", "clipboard"]));
+    tools.push(("pbcopy", &[]));
+    let Some(&(program, args)) = tools
+        .iter()
+        .find(|(program, _)| is_command_available(program))
+    else {
+        let names: Vec<&str> = tools.iter().map(|(program, _)| *program).collect();
+        return Err(io::Error::other(format!(
+            "No clipboard tool found, install one of: {}",
+            names.join(", ")
+        )));
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("Clipboard stdin is piped")
+        .write_all(text.as_bytes())?;
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} {}", program, status)))
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    io::{self, BufRead, Write},
+    process::{Command, Stdio},
+};
+
+use crate::config::PickerKind;
+
+use super::fzf::{prompt_user, skim_menu};
+
+/// A way of letting the user choose from a list or type a line
+pub trait Picker {
+    /// One of `options`, or `None` when the picker was dismissed
+    fn pick(&self, options: &[&str], prompt: Option<&str>) -> Option<String>;
+    /// Free text typed by the user
+    fn prompt(&self, prompt: &str) -> String;
+}
+
+/// Build the picker selected in the configuration or with `--picker`
+pub fn picker_for(kind: &PickerKind) -> Box<dyn Picker> {
+    match kind {
+        PickerKind::Skim => Box::new(Skim),
+        PickerKind::Fzf => Box::new(Fzf),
+        PickerKind::Rofi => Box::new(Dmenu {
+            program: "rofi",
+            args: &["-dmenu", "-i"],
+            pick_args: &["-no-custom"],
+        }),
+        PickerKind::Dmenu => Box::new(Dmenu {
+            program: "dmenu",
+            args: &["-i"],
+            pick_args: &[],
+        }),
+        PickerKind::Bemenu => Box::new(Dmenu {
+            program: "bemenu",
+            args: &["-i"],
+            pick_args: &[],
+        }),
+        PickerKind::Stdin => Box::new(Stdin),
+    }
+}
+
+/// Embedded skim, escape dismisses the menu
+pub struct Skim;
+
+impl Picker for Skim {
+    fn pick(&self, options: &[&str], prompt: Option<&str>) -> Option<String> {
+        skim_menu(options, prompt)
+    }
+
+    fn prompt(&self, prompt: &str) -> String {
+        prompt_user(prompt)
+    }
+}
+
+/// External `fzf` binary
+pub struct Fzf;
+
+impl Picker for Fzf {
+    fn pick(&self, options: &[&str], prompt: Option<&str>) -> Option<String> {
+        let mut args = vec!["--reverse"];
+        if let Some(prompt) = prompt {
+            args.extend(["--prompt", prompt]);
+        }
+        run_picker("fzf", &args, &options.join("\n"))
+    }
+
+    fn prompt(&self, prompt: &str) -> String {
+        // With nothing to match, fzf exits with failure but still prints the query first
+        let output = Command::new("fzf")
+            .args(["--reverse", "--print-query", "--prompt", prompt])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output();
+        output
+            .ok()
+            .and_then(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .map(|line| line.to_owned())
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// `dmenu` and launchers speaking its protocol, options on stdin and the choice on stdout
+pub struct Dmenu {
+    program: &'static str,
+    args: &'static [&'static str],
+    /// Only when choosing from options, typing a query needs free text
+    pick_args: &'static [&'static str],
+}
+
+impl Picker for Dmenu {
+    fn pick(&self, options: &[&str], prompt: Option<&str>) -> Option<String> {
+        let mut args = self.args.to_vec();
+        args.extend(self.pick_args);
+        if let Some(prompt) = prompt {
+            args.extend(["-p", prompt]);
+        }
+        run_picker(self.program, &args, &options.join("\n"))
+    }
+
+    fn prompt(&self, prompt: &str) -> String {
+        let mut args = self.args.to_vec();
+        args.extend(["-p", prompt]);
+        run_picker(self.program, &args, "").unwrap_or_default()
+    }
+}
+
+/// Numbered list on stderr, answered with a number or the option itself on stdin
+pub struct Stdin;
+
+impl Picker for Stdin {
+    fn pick(&self, options: &[&str], prompt: Option<&str>) -> Option<String> {
+        for (index, option) in options.iter().enumerate() {
+            eprintln!("{:>3}) {}", index + 1, option);
+        }
+        loop {
+            let answer = read_line(prompt.unwrap_or("> "))?;
+            let chosen = match answer.parse::<usize>() {
+                Ok(number) => number.checked_sub(1).and_then(|index| options.get(index)),
+                Err(_) => options.iter().find(|&&option| option == answer),
+            };
+            match chosen {
+                Some(option) => return Some(option.to_string()),
+                None => eprintln!("Enter a number from 1 to {}", options.len()),
+            }
+        }
+    }
+
+    fn prompt(&self, prompt: &str) -> String {
+        read_line(prompt).unwrap_or_default()
+    }
+}
+
+/// Feed `input` to a picker program and return the line it prints, `None` if dismissed
+fn run_picker(program: &str, args: &[&str], input: &str) -> Option<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| eprintln!("Failed to run {}: {}", program, e))
+        .ok()?;
+    // The picker may quit before reading everything, which is not an error
+    let _ = child
+        .stdin
+        .take()
+        .expect("Picker stdin is piped")
+        .write_all(input.as_bytes());
+    let output = child.wait_with_output().ok()?;
+    let selection = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_owned();
+    (output.status.success() && !selection.is_empty()).then_some(selection)
+}
+
+/// `None` once stdin is closed
+fn read_line(prompt: &str) -> Option<String> {
+    eprint!("{} ", prompt.trim_end());
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_owned()),
+    }
+}